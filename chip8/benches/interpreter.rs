@@ -0,0 +1,32 @@
+use chip8::{Chip8, Quirks};
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+
+const TETRIS: &[u8] = include_bytes!("../../examples/tetris.ch8");
+const FRAMES: u32 = 600;
+const INSTRUCTIONS_PER_FRAME: u32 = 100;
+
+/// Runs tetris for ten seconds of emulated time, with the decode cache on or off
+fn run_tetris(cached: bool) -> Chip8 {
+    // Without the display wait quirk every frame runs all its instructions
+    let mut chip8 = Chip8::new(Quirks::chip48());
+    chip8.set_decode_cache(cached);
+    chip8.load_rom(TETRIS).unwrap();
+    for frame in 0..FRAMES {
+        chip8.keypress(0x5, frame % 50 < 10);
+        chip8.run_frame(INSTRUCTIONS_PER_FRAME).unwrap();
+    }
+    chip8
+}
+
+fn interpreter(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tetris");
+    group.throughput(Throughput::Elements(u64::from(
+        FRAMES * INSTRUCTIONS_PER_FRAME,
+    )));
+    group.bench_function("uncached", |b| b.iter(|| run_tetris(false)));
+    group.bench_function("decode_cache", |b| b.iter(|| run_tetris(true)));
+    group.finish();
+}
+
+criterion_group!(benches, interpreter);
+criterion_main!(benches);
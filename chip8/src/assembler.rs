@@ -0,0 +1,835 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::OPS_START_ADDRESS;
+use crate::instruction::Instruction;
+
+/// Mnemonics and directives understood by the assembler
+const MNEMONICS: [&str; 34] = [
+    "CLS", "RET", "SCD", "SCR", "SCL", "EXIT", "LOW", "HIGH", "SYS", "JP", "CALL", "SE", "SNE",
+    "SAVE", "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN", "SHL", "RND", "DRW",
+    "SKP", "SKNP", "PLANE", "AUDIO", "PITCH", "DB", "DW", "INCLUDE",
+];
+
+/// What went wrong while assembling, see [`AssembleError`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssembleErrorKind {
+    /// The mnemonic or directive is not known
+    UnknownMnemonic(String),
+    /// The operands don't match any form of the mnemonic
+    InvalidOperands(String),
+    /// An operand is not a number, symbol or sum of them
+    InvalidExpression(String),
+    /// A symbol was used but never defined
+    UndefinedSymbol(String),
+    /// A label or constant was defined more than once
+    DuplicateSymbol(String),
+    /// A constant is defined in terms of itself
+    RecursiveSymbol(String),
+    /// A value does not fit in its operand
+    ValueOutOfRange { value: i64, max: u16 },
+    /// A string literal is missing its closing quote
+    UnterminatedString,
+    /// A file could not be read, or includes itself
+    Include { path: PathBuf, reason: String },
+    /// A token that can't appear at this point
+    UnexpectedToken(String),
+    /// The source ended in the middle of a statement
+    UnexpectedEnd,
+    /// A block opened by this token is never closed
+    Unclosed(String),
+    /// A construct of the language that is not supported
+    Unsupported(String),
+}
+
+impl fmt::Display for AssembleErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssembleErrorKind::UnknownMnemonic(name) => write!(f, "unknown mnemonic {}", name),
+            AssembleErrorKind::InvalidOperands(name) => write!(f, "invalid operands for {}", name),
+            AssembleErrorKind::InvalidExpression(text) => {
+                write!(f, "invalid expression {:?}", text)
+            }
+            AssembleErrorKind::UndefinedSymbol(name) => write!(f, "undefined symbol {}", name),
+            AssembleErrorKind::DuplicateSymbol(name) => {
+                write!(f, "symbol {} is already defined", name)
+            }
+            AssembleErrorKind::RecursiveSymbol(name) => {
+                write!(f, "symbol {} is defined in terms of itself", name)
+            }
+            AssembleErrorKind::ValueOutOfRange { value, max } => {
+                write!(f, "value {} is out of range 0..={:#x}", value, max)
+            }
+            AssembleErrorKind::UnterminatedString => write!(f, "unterminated string"),
+            AssembleErrorKind::Include { path, reason } => {
+                write!(f, "cannot include {}: {}", path.display(), reason)
+            }
+            AssembleErrorKind::UnexpectedToken(text) => write!(f, "unexpected {:?}", text),
+            AssembleErrorKind::UnexpectedEnd => write!(f, "unexpected end of source"),
+            AssembleErrorKind::Unclosed(text) => write!(f, "{} is never closed", text),
+            AssembleErrorKind::Unsupported(text) => write!(f, "{} is not supported", text),
+        }
+    }
+}
+
+/// Error raised by [`assemble`], [`assemble_file`] and
+/// [`compile_octo`](crate::compile_octo). Lines and columns
+/// count from 1; `file` is `None` for source passed in as a string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    pub column: usize,
+    pub kind: AssembleErrorKind,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file.display())?;
+        }
+        write!(f, "{}:{}: {}", self.line, self.column, self.kind)
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+/// Assembles source in the classic syntax into a ROM loaded at 0x200.
+///
+/// Each line holds an optional `label:`, then an instruction or directive,
+/// and comments start with `;`. Mnemonics and registers are case
+/// insensitive and match the [`Syntax::Classic`](crate::Syntax::Classic)
+/// output of the disassembler. Besides instructions the assembler knows:
+///
+/// | Directive             | Effect                                           |
+/// |-----------------------|--------------------------------------------------|
+/// | `NAME EQU expr`       | defines a constant                               |
+/// | `DB expr, "text"`     | emits bytes                                      |
+/// | `DW expr`             | emits big endian words                           |
+/// | `INCLUDE "file"`      | assembles another file in place                  |
+///
+/// Expressions are sums and differences of numbers (`12`, `0x0C`, `$0C`,
+/// `0b1100`, `%1100`) and symbols, which may be used before they're defined.
+/// `SHR VX` and `SHL VX` without a second register shift VX into itself.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssembleError> {
+    let mut assembler = Assembler::default();
+    assembler.parse_source(source, None)?;
+    assembler.emit()
+}
+
+/// Assembles the file at `path`, see [`assemble`]. Included files are
+/// looked up relative to the file that includes them.
+pub fn assemble_file(path: impl AsRef<Path>) -> Result<Vec<u8>, AssembleError> {
+    let mut assembler = Assembler::default();
+    assembler.include(path.as_ref(), None)?;
+    assembler.emit()
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Pos {
+    /// Index into `Assembler::files`
+    file: Option<usize>,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Operand {
+    text: String,
+    pos: Pos,
+}
+
+#[derive(Debug)]
+enum StatementKind {
+    Instruction(String, Vec<Operand>),
+    Bytes(Vec<Operand>),
+    Words(Vec<Operand>),
+}
+
+#[derive(Debug)]
+struct Statement {
+    pos: Pos,
+    kind: StatementKind,
+}
+
+#[derive(Debug)]
+enum Symbol {
+    Label(u32),
+    Constant(Operand),
+}
+
+/// A decoded operand of an instruction
+#[derive(Debug, Clone, Copy)]
+enum Arg {
+    V(u8),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Long(i64, Pos),
+    Value(i64, Pos),
+}
+
+#[derive(Debug, Default)]
+struct Assembler {
+    files: Vec<PathBuf>,
+    /// Files being included, innermost last, to catch include cycles
+    include_stack: Vec<PathBuf>,
+    statements: Vec<Statement>,
+    symbols: HashMap<String, Symbol>,
+    /// Address of the next statement, relative to 0x200
+    size: u32,
+}
+
+impl Assembler {
+    fn error(&self, pos: Pos, kind: AssembleErrorKind) -> AssembleError {
+        AssembleError {
+            file: pos.file.map(|file| self.files[file].clone()),
+            line: pos.line,
+            column: pos.column,
+            kind,
+        }
+    }
+
+    fn include(&mut self, path: &Path, from: Option<Pos>) -> Result<(), AssembleError> {
+        let pos = from.unwrap_or(Pos {
+            file: None,
+            line: 1,
+            column: 1,
+        });
+        let include_error = |reason: String| AssembleErrorKind::Include {
+            path: path.to_path_buf(),
+            reason,
+        };
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        if self.include_stack.contains(&canonical) {
+            return Err(self.error(pos, include_error("file includes itself".to_string())));
+        }
+        let source = fs::read_to_string(path)
+            .map_err(|err| self.error(pos, include_error(err.to_string())))?;
+        self.files.push(path.to_path_buf());
+        self.include_stack.push(canonical);
+        let result = self.parse_source(&source, Some(self.files.len() - 1));
+        self.include_stack.pop();
+        result
+    }
+
+    fn parse_source(&mut self, source: &str, file: Option<usize>) -> Result<(), AssembleError> {
+        for (index, line) in source.lines().enumerate() {
+            self.parse_line(line, file, index + 1)?;
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol, pos: Pos) -> Result<(), AssembleError> {
+        if self.symbols.contains_key(name) {
+            return Err(self.error(pos, AssembleErrorKind::DuplicateSymbol(name.to_string())));
+        }
+        self.symbols.insert(name.to_string(), symbol);
+        Ok(())
+    }
+
+    fn parse_line(
+        &mut self,
+        line: &str,
+        file: Option<usize>,
+        number: usize,
+    ) -> Result<(), AssembleError> {
+        let pos = |offset: usize| Pos {
+            file,
+            line: number,
+            column: line[..offset].chars().count() + 1,
+        };
+        let code = strip_comment(line)
+            .map_err(|offset| self.error(pos(offset), AssembleErrorKind::UnterminatedString))?;
+
+        let mut start = skip_whitespace(code, 0);
+        let label_len = identifier_len(&code[start..]);
+        if label_len > 0 && code[start + label_len..].starts_with(':') {
+            let name = &code[start..start + label_len];
+            self.define(name, Symbol::Label(self.size), pos(start))?;
+            start = skip_whitespace(code, start + label_len + 1);
+        }
+
+        let word_end = code[start..]
+            .find(char::is_whitespace)
+            .map_or(code.len(), |len| start + len);
+        let word = &code[start..word_end];
+        if word.is_empty() {
+            return Ok(());
+        }
+
+        let next = skip_whitespace(code, word_end);
+        let next_end = code[next..]
+            .find(char::is_whitespace)
+            .map_or(code.len(), |len| next + len);
+        if code[next..next_end].eq_ignore_ascii_case("EQU") {
+            if identifier_len(word) != word.len() {
+                let kind = AssembleErrorKind::InvalidExpression(word.to_string());
+                return Err(self.error(pos(start), kind));
+            }
+            let value_start = skip_whitespace(code, next_end);
+            let value = Operand {
+                text: code[value_start..].trim_end().to_string(),
+                pos: pos(value_start),
+            };
+            return self.define(word, Symbol::Constant(value), pos(start));
+        }
+
+        let operands: Vec<Operand> = split_operands(code, word_end)
+            .into_iter()
+            .map(|(offset, text)| Operand {
+                text: text.to_string(),
+                pos: pos(offset),
+            })
+            .collect();
+        let mnemonic = word.to_ascii_uppercase();
+        let (kind, size) = match mnemonic.as_str() {
+            "DB" => {
+                let size = operands
+                    .iter()
+                    .map(|operand| string_literal(&operand.text).map_or(1, str::len))
+                    .sum::<usize>();
+                (StatementKind::Bytes(operands), size)
+            }
+            "DW" => {
+                let size = operands.len() * 2;
+                (StatementKind::Words(operands), size)
+            }
+            "INCLUDE" => {
+                let path = match operands.as_slice() {
+                    [operand] => string_literal(&operand.text),
+                    _ => None,
+                };
+                let Some(path) = path else {
+                    let kind = AssembleErrorKind::InvalidOperands(mnemonic);
+                    return Err(self.error(pos(start), kind));
+                };
+                let path = match file.and_then(|file| self.files[file].parent()) {
+                    Some(dir) => dir.join(path),
+                    None => PathBuf::from(path),
+                };
+                return self.include(&path, Some(pos(start)));
+            }
+            _ => {
+                let long = match operands.as_slice() {
+                    [dest, src] => {
+                        mnemonic == "LD"
+                            && dest.text.eq_ignore_ascii_case("I")
+                            && long_operand(&src.text).is_some()
+                    }
+                    _ => false,
+                };
+                let size = if long { 4 } else { 2 };
+                (StatementKind::Instruction(mnemonic, operands), size)
+            }
+        };
+        self.statements.push(Statement {
+            pos: pos(start),
+            kind,
+        });
+        self.size += size as u32;
+        Ok(())
+    }
+
+    fn emit(&self) -> Result<Vec<u8>, AssembleError> {
+        let mut rom = Vec::with_capacity(self.size as usize);
+        for statement in &self.statements {
+            match &statement.kind {
+                StatementKind::Instruction(mnemonic, operands) => {
+                    let (ins, long) = self.instruction(mnemonic, operands, statement.pos)?;
+                    let opcode = u16::try_from(ins).map_err(|_| {
+                        let kind = AssembleErrorKind::InvalidOperands(mnemonic.clone());
+                        self.error(statement.pos, kind)
+                    })?;
+                    rom.extend_from_slice(&opcode.to_be_bytes());
+                    if let Some(addr) = long {
+                        rom.extend_from_slice(&addr.to_be_bytes());
+                    }
+                }
+                StatementKind::Bytes(operands) => {
+                    for operand in operands {
+                        match string_literal(&operand.text) {
+                            Some(text) => rom.extend_from_slice(text.as_bytes()),
+                            None => rom.push(self.value(operand, 0xFF)? as u8),
+                        }
+                    }
+                }
+                StatementKind::Words(operands) => {
+                    for operand in operands {
+                        rom.extend_from_slice(&self.value(operand, 0xFFFF)?.to_be_bytes());
+                    }
+                }
+            }
+        }
+        Ok(rom)
+    }
+
+    /// Evaluates `operand` and checks it lies in `0..=max`
+    fn value(&self, operand: &Operand, max: u16) -> Result<u16, AssembleError> {
+        let value = self.eval(operand, &mut Vec::new())?;
+        self.check(value, max, operand.pos)
+    }
+
+    fn check(&self, value: i64, max: u16, pos: Pos) -> Result<u16, AssembleError> {
+        if (0..=i64::from(max)).contains(&value) {
+            Ok(value as u16)
+        } else {
+            Err(self.error(pos, AssembleErrorKind::ValueOutOfRange { value, max }))
+        }
+    }
+
+    /// Evaluates an expression. `resolving` holds the constants whose values
+    /// are being computed, so that cycles are reported instead of overflowing
+    /// the stack.
+    fn eval(&self, operand: &Operand, resolving: &mut Vec<String>) -> Result<i64, AssembleError> {
+        let invalid = || {
+            let kind = AssembleErrorKind::InvalidExpression(operand.text.clone());
+            self.error(operand.pos, kind)
+        };
+        let text = operand.text.as_str();
+        let mut total = 0i64;
+        let mut sign = 1i64;
+        let mut offset = skip_whitespace(text, 0);
+        if let Some(rest) = text[offset..].strip_prefix('-') {
+            sign = -1;
+            offset = text.len() - rest.len();
+        }
+        loop {
+            offset = skip_whitespace(text, offset);
+            let rest = &text[offset..];
+            let len = rest
+                .find(|ch: char| ch.is_whitespace() || ch == '+' || ch == '-')
+                .unwrap_or(rest.len());
+            let term = &rest[..len];
+            if term.is_empty() {
+                return Err(invalid());
+            }
+            let value = match parse_number(term) {
+                Some(value) => value,
+                None if identifier_len(term) == term.len() => {
+                    self.resolve(term, operand.pos, resolving)?
+                }
+                None => return Err(invalid()),
+            };
+            total = sign
+                .checked_mul(value)
+                .and_then(|term| total.checked_add(term))
+                .ok_or_else(|| {
+                    // Report the side the sum ran off
+                    let value = if (sign > 0) == (value >= 0) {
+                        i64::MAX
+                    } else {
+                        i64::MIN
+                    };
+                    let kind = AssembleErrorKind::ValueOutOfRange {
+                        value,
+                        max: u16::MAX,
+                    };
+                    self.error(operand.pos, kind)
+                })?;
+
+            offset = skip_whitespace(text, offset + len);
+            match text[offset..].chars().next() {
+                None => return Ok(total),
+                Some('+') => sign = 1,
+                Some('-') => sign = -1,
+                Some(_) => return Err(invalid()),
+            }
+            offset += 1;
+        }
+    }
+
+    fn resolve(
+        &self,
+        name: &str,
+        pos: Pos,
+        resolving: &mut Vec<String>,
+    ) -> Result<i64, AssembleError> {
+        match self.symbols.get(name) {
+            Some(Symbol::Label(offset)) => Ok(i64::from(OPS_START_ADDRESS) + i64::from(*offset)),
+            Some(Symbol::Constant(value)) => {
+                if resolving.iter().any(|other| other == name) {
+                    let kind = AssembleErrorKind::RecursiveSymbol(name.to_string());
+                    return Err(self.error(pos, kind));
+                }
+                resolving.push(name.to_string());
+                let result = self.eval(value, resolving);
+                resolving.pop();
+                result
+            }
+            None => Err(self.error(pos, AssembleErrorKind::UndefinedSymbol(name.to_string()))),
+        }
+    }
+
+    fn arg(&self, operand: &Operand) -> Result<Arg, AssembleError> {
+        let upper = operand.text.to_ascii_uppercase();
+        let arg = match upper.as_str() {
+            "I" => Arg::I,
+            "[I]" => Arg::IndirectI,
+            "DT" => Arg::Dt,
+            "ST" => Arg::St,
+            "K" => Arg::K,
+            "F" => Arg::F,
+            "HF" => Arg::Hf,
+            "B" => Arg::B,
+            "R" => Arg::R,
+            _ => {
+                if let Some(reg) = register(&upper) {
+                    Arg::V(reg)
+                } else if let Some(offset) = long_operand(&operand.text) {
+                    let value = Operand {
+                        text: operand.text[offset..].to_string(),
+                        pos: Pos {
+                            column: operand.pos.column + offset,
+                            ..operand.pos
+                        },
+                    };
+                    Arg::Long(self.eval(&value, &mut Vec::new())?, value.pos)
+                } else {
+                    Arg::Value(self.eval(operand, &mut Vec::new())?, operand.pos)
+                }
+            }
+        };
+        Ok(arg)
+    }
+
+    /// Builds the instruction for a mnemonic, plus the address word that
+    /// follows `LD I, LONG`
+    fn instruction(
+        &self,
+        mnemonic: &str,
+        operands: &[Operand],
+        pos: Pos,
+    ) -> Result<(Instruction, Option<u16>), AssembleError> {
+        let args = operands
+            .iter()
+            .map(|operand| self.arg(operand))
+            .collect::<Result<Vec<_>, _>>()?;
+        let nibble = |value: i64, pos: Pos| self.check(value, 0xF, pos).map(|n| n as u8);
+        let byte = |value: i64, pos: Pos| self.check(value, 0xFF, pos).map(|n| n as u8);
+        let addr = |value: i64, pos: Pos| self.check(value, 0xFFF, pos);
+
+        use Arg::*;
+        let ins = match (mnemonic, args.as_slice()) {
+            ("CLS", []) => Instruction::Cls,
+            ("RET", []) => Instruction::Ret,
+            ("SCD", [Value(n, p)]) => Instruction::ScrollDown(nibble(*n, *p)?),
+            ("SCR", []) => Instruction::ScrollRight,
+            ("SCL", []) => Instruction::ScrollLeft,
+            ("EXIT", []) => Instruction::Exit,
+            ("LOW", []) => Instruction::Lores,
+            ("HIGH", []) => Instruction::Hires,
+            ("SYS", [Value(a, p)]) => Instruction::Sys(addr(*a, *p)?),
+            ("JP", [Value(a, p)]) => Instruction::Jump(addr(*a, *p)?),
+            ("JP", [V(0), Value(a, p)]) => Instruction::JumpV0(addr(*a, *p)?),
+            ("CALL", [Value(a, p)]) => Instruction::Call(addr(*a, *p)?),
+            ("SE", [V(x), Value(n, p)]) => Instruction::SkipEqByte(*x, byte(*n, *p)?),
+            ("SE", [V(x), V(y)]) => Instruction::SkipEqReg(*x, *y),
+            ("SNE", [V(x), Value(n, p)]) => Instruction::SkipNeByte(*x, byte(*n, *p)?),
+            ("SNE", [V(x), V(y)]) => Instruction::SkipNeReg(*x, *y),
+            ("SAVE", [V(x), V(y)]) => Instruction::SaveRange(*x, *y),
+            ("LOAD", [V(x), V(y)]) => Instruction::LoadRange(*x, *y),
+            ("LD", [V(x), Value(n, p)]) => Instruction::LoadByte(*x, byte(*n, *p)?),
+            ("LD", [V(x), V(y)]) => Instruction::LoadReg(*x, *y),
+            ("LD", [I, Value(a, p)]) => Instruction::LoadI(addr(*a, *p)?),
+            ("LD", [I, Long(a, p)]) => {
+                return Ok((Instruction::LoadILong, Some(self.check(*a, 0xFFFF, *p)?)));
+            }
+            ("LD", [V(x), Dt]) => Instruction::LoadDT(*x),
+            ("LD", [V(x), K]) => Instruction::WaitKey(*x),
+            ("LD", [Dt, V(x)]) => Instruction::SetDT(*x),
+            ("LD", [St, V(x)]) => Instruction::SetST(*x),
+            ("LD", [F, V(x)]) => Instruction::LoadSprite(*x),
+            ("LD", [Hf, V(x)]) => Instruction::LoadBigSprite(*x),
+            ("LD", [B, V(x)]) => Instruction::Bcd(*x),
+            ("LD", [IndirectI, V(x)]) => Instruction::DumpRegs(*x),
+            ("LD", [V(x), IndirectI]) => Instruction::LoadRegs(*x),
+            ("LD", [R, V(x)]) => Instruction::StoreFlags(*x),
+            ("LD", [V(x), R]) => Instruction::LoadFlags(*x),
+            ("ADD", [V(x), Value(n, p)]) => Instruction::AddByte(*x, byte(*n, *p)?),
+            ("ADD", [V(x), V(y)]) => Instruction::AddReg(*x, *y),
+            ("ADD", [I, V(x)]) => Instruction::AddI(*x),
+            ("OR", [V(x), V(y)]) => Instruction::OrReg(*x, *y),
+            ("AND", [V(x), V(y)]) => Instruction::AndReg(*x, *y),
+            ("XOR", [V(x), V(y)]) => Instruction::XorReg(*x, *y),
+            ("SUB", [V(x), V(y)]) => Instruction::SubReg(*x, *y),
+            ("SHR", [V(x)]) => Instruction::ShrReg(*x, *x),
+            ("SHR", [V(x), V(y)]) => Instruction::ShrReg(*x, *y),
+            ("SUBN", [V(x), V(y)]) => Instruction::SubnReg(*x, *y),
+            ("SHL", [V(x)]) => Instruction::ShlReg(*x, *x),
+            ("SHL", [V(x), V(y)]) => Instruction::ShlReg(*x, *y),
+            ("RND", [V(x), Value(n, p)]) => Instruction::Rand(*x, byte(*n, *p)?),
+            ("DRW", [V(x), V(y), Value(n, p)]) => Instruction::Draw(*x, *y, nibble(*n, *p)?),
+            ("SKP", [V(x)]) => Instruction::SkipIfKey(*x),
+            ("SKNP", [V(x)]) => Instruction::SkipIfNotKey(*x),
+            ("PLANE", [Value(n, p)]) => Instruction::Plane(nibble(*n, *p)?),
+            ("AUDIO", []) => Instruction::AudioPattern,
+            ("PITCH", [V(x)]) => Instruction::Pitch(*x),
+            _ if MNEMONICS.contains(&mnemonic) => {
+                let kind = AssembleErrorKind::InvalidOperands(mnemonic.to_string());
+                return Err(self.error(pos, kind));
+            }
+            _ => {
+                let kind = AssembleErrorKind::UnknownMnemonic(mnemonic.to_string());
+                return Err(self.error(pos, kind));
+            }
+        };
+        Ok((ins, None))
+    }
+}
+
+/// Cuts a line at the `;` starting its comment, or returns the offset of an
+/// unterminated string
+fn strip_comment(line: &str) -> Result<&str, usize> {
+    let mut quote = None;
+    for (offset, ch) in line.char_indices() {
+        match (ch, quote) {
+            ('"', None) => quote = Some(offset),
+            ('"', Some(_)) => quote = None,
+            (';', None) => return Ok(&line[..offset]),
+            _ => {}
+        }
+    }
+    match quote {
+        Some(offset) => Err(offset),
+        None => Ok(line),
+    }
+}
+
+fn skip_whitespace(text: &str, offset: usize) -> usize {
+    let rest = &text[offset..];
+    offset + rest.len() - rest.trim_start().len()
+}
+
+/// Length of the identifier at the start of `text`, 0 if there is none
+fn identifier_len(text: &str) -> usize {
+    let mut chars = text.char_indices();
+    match chars.next() {
+        Some((_, ch)) if ch.is_ascii_alphabetic() || ch == '_' => {}
+        _ => return 0,
+    }
+    chars
+        .find(|(_, ch)| !(ch.is_ascii_alphanumeric() || *ch == '_' || *ch == '.'))
+        .map_or(text.len(), |(offset, _)| offset)
+}
+
+/// Splits the text after `offset` at commas outside of strings, returning
+/// each trimmed operand with its offset in the line
+fn split_operands(code: &str, offset: usize) -> Vec<(usize, &str)> {
+    if code[offset..].trim().is_empty() {
+        return Vec::new();
+    }
+    let mut operands = Vec::new();
+    let mut start = offset;
+    let mut in_string = false;
+    for (index, ch) in code[offset..].char_indices() {
+        match ch {
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                operands.push(start..offset + index);
+                start = offset + index + 1;
+            }
+            _ => {}
+        }
+    }
+    operands.push(start..code.len());
+    operands
+        .into_iter()
+        .map(|range| {
+            let start = skip_whitespace(code, range.start);
+            (start, code[start.min(range.end)..range.end].trim_end())
+        })
+        .collect()
+}
+
+fn string_literal(text: &str) -> Option<&str> {
+    text.strip_prefix('"')?.strip_suffix('"')
+}
+
+/// Offset of the address in a `LONG addr` operand
+fn long_operand(text: &str) -> Option<usize> {
+    let keyword = text.get(..5)?;
+    if keyword.eq_ignore_ascii_case("LONG ") {
+        Some(skip_whitespace(text, 5))
+    } else {
+        None
+    }
+}
+
+fn register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('V')?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let lower = text.to_ascii_lowercase();
+    let (digits, radix) = if let Some(hex) = lower.strip_prefix("0x") {
+        (hex, 16)
+    } else if let Some(hex) = lower.strip_prefix('$') {
+        (hex, 16)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        (bin, 2)
+    } else if let Some(bin) = lower.strip_prefix('%') {
+        (bin, 2)
+    } else if lower.starts_with(|ch: char| ch.is_ascii_digit()) {
+        (lower.as_str(), 10)
+    } else {
+        return None;
+    };
+    i64::from_str_radix(digits, radix).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::Syntax;
+
+    fn error_at(source: &str) -> (usize, usize, AssembleErrorKind) {
+        let err = assemble(source).unwrap_err();
+        (err.line, err.column, err.kind)
+    }
+
+    #[test]
+    fn test_assemble_program() {
+        let source = "\
+; draw a digit forever
+DIGIT EQU 7
+start:  LD V0, DIGIT
+        LD F, V0
+        DRW V1, V2, 5
+loop:   JP loop
+        DB 0xFF, %1010, \"AB\"
+        DW sprite + 2
+sprite: DB $F0
+";
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![
+                0x60, 0x07, 0xF0, 0x29, 0xD1, 0x25, 0x12, 0x06, 0xFF, 0x0A, 0x41, 0x42, 0x02, 0x10,
+                0xF0,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_long_load() {
+        assert_eq!(
+            assemble("ld i, long data\nexit\ndata: db 1").unwrap(),
+            vec![0xF0, 0x00, 0x02, 0x06, 0x00, 0xFD, 0x01]
+        );
+    }
+
+    #[test]
+    fn test_round_trip_disassembler_mnemonics() {
+        for opcode in 0..=u16::MAX {
+            let ins = Instruction::from(opcode);
+            if ins == Instruction::NoOp || ins == Instruction::LoadILong {
+                continue;
+            }
+            let source = ins.mnemonic(Syntax::Classic);
+            assert_eq!(
+                assemble(&source).unwrap(),
+                opcode.to_be_bytes(),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            error_at("CLS\n  FOO V1"),
+            (2, 3, AssembleErrorKind::UnknownMnemonic("FOO".to_string()))
+        );
+        assert_eq!(
+            error_at("LD V1, I"),
+            (1, 1, AssembleErrorKind::InvalidOperands("LD".to_string()))
+        );
+        assert_eq!(
+            error_at("LD V1, 256"),
+            (
+                1,
+                8,
+                AssembleErrorKind::ValueOutOfRange {
+                    value: 256,
+                    max: 0xFF
+                }
+            )
+        );
+        assert_eq!(
+            error_at("DB 0x7FFFFFFFFFFFFFFF + 1"),
+            (
+                1,
+                4,
+                AssembleErrorKind::ValueOutOfRange {
+                    value: i64::MAX,
+                    max: 0xFFFF
+                }
+            )
+        );
+        assert_eq!(
+            error_at("DB -0x7FFFFFFFFFFFFFFF - 2"),
+            (
+                1,
+                4,
+                AssembleErrorKind::ValueOutOfRange {
+                    value: i64::MIN,
+                    max: 0xFFFF
+                }
+            )
+        );
+        assert_eq!(
+            error_at("JP nowhere"),
+            (
+                1,
+                4,
+                AssembleErrorKind::UndefinedSymbol("nowhere".to_string())
+            )
+        );
+        assert_eq!(
+            error_at("a: CLS\na: CLS"),
+            (2, 1, AssembleErrorKind::DuplicateSymbol("a".to_string()))
+        );
+        assert_eq!(
+            error_at("A EQU B\nB EQU A\nJP A"),
+            (2, 7, AssembleErrorKind::RecursiveSymbol("A".to_string()))
+        );
+        assert_eq!(
+            error_at("DB \"abc"),
+            (1, 4, AssembleErrorKind::UnterminatedString)
+        );
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(format!("chip8-asm-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("main.asm"),
+            "CALL sub\nEXIT\nINCLUDE \"sub.asm\"\n",
+        )
+        .unwrap();
+        fs::write(dir.join("sub.asm"), "sub: RET\n").unwrap();
+        fs::write(dir.join("loop.asm"), "INCLUDE \"loop.asm\"\n").unwrap();
+
+        let rom = assemble_file(dir.join("main.asm"));
+        let err = assemble_file(dir.join("loop.asm")).unwrap_err();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(rom.unwrap(), vec![0x22, 0x04, 0x00, 0xFD, 0x00, 0xEE]);
+        assert_eq!(err.file, Some(dir.join("loop.asm")));
+        assert!(matches!(err.kind, AssembleErrorKind::Include { .. }));
+    }
+}
@@ -0,0 +1,217 @@
+use std::f32::consts::TAU;
+use std::io::{self, Write};
+
+use crate::Chip8;
+
+/// Pitch of the buzzer unless configured otherwise
+pub const DEFAULT_FREQUENCY: f32 = 440.0;
+
+/// Shape of the tone played while the sound timer is running
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Waveform {
+    #[default]
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+    /// The XO-CHIP 1-bit audio pattern, played at the rate set by 0xFX3A.
+    /// The frequency of the [`Buzzer`] is ignored.
+    Pattern,
+}
+
+/// Generates PCM samples for the buzzer of a [`Chip8`]. Samples are mono
+/// and lie in -1.0..=1.0.
+#[derive(Debug, Clone)]
+pub struct Buzzer {
+    sample_rate: u32,
+    /// Tone frequency in Hz
+    pub frequency: f32,
+    pub waveform: Waveform,
+    /// Amplitude between 0.0 and 1.0
+    pub volume: f32,
+    /// Position within the current period, from 0.0 to 1.0
+    phase: f32,
+}
+
+impl Buzzer {
+    pub fn new(sample_rate: u32) -> Self {
+        Buzzer {
+            sample_rate,
+            frequency: DEFAULT_FREQUENCY,
+            waveform: Waveform::default(),
+            volume: 0.5,
+            phase: 0.0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Number of samples covering one 60 Hz frame
+    pub fn samples_per_frame(&self) -> usize {
+        (self.sample_rate / 60) as usize
+    }
+
+    /// Fills `out` with the sound of `chip8` in its current state: the tone
+    /// while [`Chip8::is_beeping`], silence otherwise. The phase carries over
+    /// between calls so consecutive buffers join without clicks.
+    pub fn fill(&mut self, chip8: &Chip8, out: &mut [f32]) {
+        if !chip8.is_beeping() {
+            self.phase = 0.0;
+            out.fill(0.0);
+            return;
+        }
+        let frequency = match self.waveform {
+            // One period plays all 128 bits of the pattern
+            Waveform::Pattern => chip8.pattern_rate() / 128.0,
+            _ => self.frequency,
+        };
+        let step = frequency / self.sample_rate as f32;
+        for sample in out {
+            *sample = self.volume * self.shape(chip8.audio_pattern());
+            self.phase = (self.phase + step).fract();
+        }
+    }
+
+    /// Samples for one 60 Hz frame, see [`Buzzer::fill`]
+    pub fn frame(&mut self, chip8: &Chip8) -> Vec<f32> {
+        let mut out = vec![0.0; self.samples_per_frame()];
+        self.fill(chip8, &mut out);
+        out
+    }
+
+    /// Value of the waveform at the current phase, at full volume
+    fn shape(&self, pattern: &[u8; 16]) -> f32 {
+        let phase = self.phase;
+        match self.waveform {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Sine => (phase * TAU).sin(),
+            Waveform::Pattern => {
+                let bit = ((phase * 128.0) as usize).min(127);
+                if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+        }
+    }
+}
+
+/// Writes mono samples in -1.0..=1.0 as a 16-bit PCM WAV file
+pub fn write_wav<W: Write>(mut out: W, sample_rate: u32, samples: &[f32]) -> io::Result<()> {
+    let data_len = u32::try_from(samples.len() * 2)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many samples for WAV"))?;
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_len).to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+    out.write_all(b"fmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    // PCM, mono
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&sample_rate.to_le_bytes())?;
+    // Byte rate and block alignment for 2 byte samples
+    out.write_all(&(sample_rate * 2).to_le_bytes())?;
+    out.write_all(&2u16.to_le_bytes())?;
+    out.write_all(&16u16.to_le_bytes())?;
+    out.write_all(b"data")?;
+    out.write_all(&data_len.to_le_bytes())?;
+    for sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16;
+        out.write_all(&value.to_le_bytes())?;
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Mode, Quirks};
+
+    #[test]
+    fn test_silent_without_sound_timer() {
+        let chip8 = Chip8::default();
+        let mut buzzer = Buzzer::new(48000);
+        let frame = buzzer.frame(&chip8);
+        assert_eq!(frame.len(), 800);
+        assert!(frame.iter().all(|&sample| sample == 0.0));
+    }
+
+    #[test]
+    fn test_square_wave() {
+        // LD V0, 2; LD ST, V0
+        let mut chip8 = Chip8::default();
+        chip8.load_rom(&[0x60, 0x02, 0xF0, 0x18]).unwrap();
+        chip8.run_frame(2).unwrap();
+        assert!(chip8.is_beeping());
+
+        let mut buzzer = Buzzer::new(8000);
+        buzzer.frequency = 1000.0;
+        buzzer.volume = 1.0;
+        let mut out = [0.0; 8];
+        buzzer.fill(&chip8, &mut out);
+        assert_eq!(out, [1.0, 1.0, 1.0, 1.0, -1.0, -1.0, -1.0, -1.0]);
+
+        chip8.tick_timers();
+        assert!(!chip8.is_beeping());
+        buzzer.fill(&chip8, &mut out);
+        assert_eq!(out, [0.0; 8]);
+    }
+
+    #[test]
+    fn test_pattern() {
+        // Pattern of alternating bytes 0xFF, 0x00 at 0x300; LD ST, V0
+        let mut chip8 = Chip8::with_mode(Mode::XoChip, Quirks::xochip());
+        let pattern: Vec<u8> = (0..16).map(|n| if n % 2 == 0 { 0xFF } else { 0 }).collect();
+        for (offset, &byte) in pattern.iter().enumerate() {
+            chip8.poke(0x300 + offset as u16, byte).unwrap();
+        }
+        chip8
+            .load_rom(&[0xA3, 0x00, 0xF0, 0x02, 0x60, 0x10, 0xF0, 0x18])
+            .unwrap();
+        chip8.run_frame(4).unwrap();
+
+        // At the default pitch of 4000 bits per second, one bit per sample
+        let mut buzzer = Buzzer::new(4000);
+        buzzer.waveform = Waveform::Pattern;
+        buzzer.volume = 1.0;
+        let mut out = [0.0; 16];
+        buzzer.fill(&chip8, &mut out);
+        assert!(out[..8].iter().all(|&sample| sample == 1.0));
+        assert!(out[8..].iter().all(|&sample| sample == -1.0));
+    }
+
+    #[test]
+    fn test_beep_rom_to_wav() {
+        let rom = include_bytes!("../../examples/timendus/7-beep.ch8");
+        let mut chip8 = Chip8::default();
+        chip8.load_rom(rom).unwrap();
+        let mut buzzer = Buzzer::new(44100);
+        let mut samples = Vec::new();
+        for _ in 0..120 {
+            chip8.run_frame(10).unwrap();
+            samples.extend(buzzer.frame(&chip8));
+        }
+        assert_eq!(samples.len(), 120 * 735);
+        assert!(samples.iter().any(|&sample| sample != 0.0));
+        assert!(samples.contains(&0.0));
+
+        let mut wav = Vec::new();
+        write_wav(&mut wav, 44100, &samples).unwrap();
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(&wav[24..28], &44100u32.to_le_bytes());
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(wav.len(), 44 + samples.len() * 2);
+    }
+}
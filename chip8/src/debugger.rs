@@ -0,0 +1,385 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+
+use crate::instruction::Instruction;
+use crate::{Chip8, Chip8Error, Mode};
+
+/// A register, as watched by the debugger and accessed through
+/// [`Chip8::register`] and [`Chip8::set_register`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Register {
+    V(u8),
+    I,
+    DelayTimer,
+    SoundTimer,
+}
+
+/// Kind of memory access that triggers a watchpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watch {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Watch {
+    fn matches(self, access: Access) -> bool {
+        matches!(
+            (self, access),
+            (Watch::ReadWrite, _) | (Watch::Read, Access::Read) | (Watch::Write, Access::Write)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Read,
+    Write,
+}
+
+/// Why the debugger stopped the program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// PC reached a breakpoint, the instruction there has not run yet
+    Breakpoint { pc: u16 },
+    /// The instruction at `pc` read a watched address
+    MemoryRead { pc: u16, addr: u16 },
+    /// The instruction at `pc` wrote a watched address
+    MemoryWrite {
+        pc: u16,
+        addr: u16,
+        old: u8,
+        new: u8,
+    },
+    /// The instruction at `pc` changed a watched register
+    RegisterChange {
+        pc: u16,
+        register: Register,
+        old: u16,
+        new: u16,
+    },
+    /// A step, step over or step out finished
+    Step,
+    /// The program has exited through 0x00FD
+    Halted,
+}
+
+/// Runs a [`Chip8`] with breakpoints, watchpoints and stepping.
+///
+/// Memory watchpoints see the data accessed by instructions, not the
+/// fetching of instructions themselves, and register watchpoints only see
+/// changes made by instructions, not the timers counting down.
+#[derive(Debug, Default)]
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    memory_watches: BTreeMap<u16, Watch>,
+    register_watches: BTreeSet<Register>,
+    /// Set by a step over or step out, which finishes once the stack is
+    /// shallower than this
+    return_depth: Option<u8>,
+    /// Breakpoint the program last stopped at, so that resuming runs it
+    resume_pc: Option<u16>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn watch_memory(&mut self, addr: u16, watch: Watch) {
+        self.memory_watches.insert(addr, watch);
+    }
+
+    pub fn unwatch_memory(&mut self, addr: u16) {
+        self.memory_watches.remove(&addr);
+    }
+
+    pub fn watch_register(&mut self, register: Register) {
+        self.register_watches.insert(register);
+    }
+
+    pub fn unwatch_register(&mut self, register: Register) {
+        self.register_watches.remove(&register);
+    }
+
+    /// Runs one 60 Hz frame like [`Chip8::run_frame`], returning early when
+    /// a breakpoint or watchpoint is hit or a step over or step out finishes.
+    /// The timers are only stepped when the whole frame runs.
+    pub fn run_frame(
+        &mut self,
+        chip8: &mut Chip8,
+        instructions_per_frame: u32,
+    ) -> Result<Option<StopReason>, Chip8Error> {
+        for _ in 0..instructions_per_frame {
+            if chip8.waiting_vblank || chip8.halted {
+                break;
+            }
+            let resuming = self.resume_pc.take() == Some(chip8.pc);
+            if !resuming && self.breakpoints.contains(&chip8.pc) {
+                self.resume_pc = Some(chip8.pc);
+                return Ok(Some(StopReason::Breakpoint { pc: chip8.pc }));
+            }
+            if let Some(reason) = self.execute(chip8)? {
+                return Ok(Some(reason));
+            }
+            if self.return_depth.is_some_and(|depth| chip8.sp < depth) {
+                self.return_depth = None;
+                return Ok(Some(StopReason::Step));
+            }
+        }
+        chip8.tick_timers();
+        Ok(None)
+    }
+
+    /// Executes a single instruction. An interpreter waiting for the display
+    /// first has its frame finished.
+    pub fn step(&mut self, chip8: &mut Chip8) -> Result<StopReason, Chip8Error> {
+        self.return_depth = None;
+        self.resume_pc = None;
+        if chip8.halted {
+            return Ok(StopReason::Halted);
+        }
+        if chip8.waiting_vblank {
+            chip8.tick_timers();
+        }
+        Ok(self.execute(chip8)?.unwrap_or(StopReason::Step))
+    }
+
+    /// Steps over a 0x2NNN call. Any other instruction is a single
+    /// [`step`](Debugger::step) and its reason is returned. For a call `None`
+    /// is returned, and the subroutine then runs through
+    /// [`run_frame`](Debugger::run_frame) until it returns, which is
+    /// reported as [`StopReason::Step`].
+    pub fn step_over(&mut self, chip8: &mut Chip8) -> Result<Option<StopReason>, Chip8Error> {
+        let is_call = matches!(next_instruction(chip8), Some(Instruction::Call(_)));
+        let depth = chip8.sp + 1;
+        let reason = self.step(chip8)?;
+        if !is_call || reason != StopReason::Step {
+            return Ok(Some(reason));
+        }
+        self.return_depth = Some(depth);
+        Ok(None)
+    }
+
+    /// Runs the current subroutine through [`run_frame`](Debugger::run_frame),
+    /// which reports [`StopReason::Step`] after its 0x00EE. Outside of a
+    /// subroutine this just resumes the program.
+    pub fn step_out(&mut self, chip8: &Chip8) {
+        self.return_depth = Some(chip8.sp);
+    }
+
+    /// Ticks `chip8` once and reports the first watchpoint hit
+    fn execute(&mut self, chip8: &mut Chip8) -> Result<Option<StopReason>, Chip8Error> {
+        let pc = chip8.pc;
+        let hit = next_instruction(chip8)
+            .and_then(|ins| memory_access(chip8, ins))
+            .and_then(|(access, range)| {
+                // Accesses past the end of memory fail in tick, only the part
+                // within memory can hit a watchpoint
+                let end = range.end.min(chip8.memory.len());
+                if range.start >= end {
+                    return None;
+                }
+                let first = u16::try_from(range.start).ok()?;
+                let last = u16::try_from(end - 1).ok()?;
+                self.memory_watches
+                    .range(first..=last)
+                    .find(|(_, watch)| watch.matches(access))
+                    .map(|(addr, _)| (access, *addr, chip8.memory[usize::from(*addr)]))
+            });
+        let registers: Vec<(Register, u16)> = self
+            .register_watches
+            .iter()
+            .map(|register| (*register, chip8.register(*register)))
+            .collect();
+
+        chip8.tick()?;
+
+        if let Some((access, addr, old)) = hit {
+            let reason = match access {
+                Access::Read => StopReason::MemoryRead { pc, addr },
+                Access::Write => StopReason::MemoryWrite {
+                    pc,
+                    addr,
+                    old,
+                    new: chip8.memory[usize::from(addr)],
+                },
+            };
+            return Ok(Some(reason));
+        }
+        let change = registers.into_iter().find_map(|(register, old)| {
+            let new = chip8.register(register);
+            (new != old).then_some(StopReason::RegisterChange {
+                pc,
+                register,
+                old,
+                new,
+            })
+        });
+        Ok(change)
+    }
+}
+
+fn next_instruction(chip8: &Chip8) -> Option<Instruction> {
+    let pc = usize::from(chip8.pc);
+    let bytes = chip8.memory.get(pc..pc + 2)?;
+    Some(Instruction::from(u16::from_be_bytes([bytes[0], bytes[1]])))
+}
+
+/// Memory that `ins` accesses through I when executed in the current state
+fn memory_access(chip8: &Chip8, ins: Instruction) -> Option<(Access, Range<usize>)> {
+    let at_i = |len: usize| usize::from(chip8.i)..usize::from(chip8.i) + len;
+    let xo = chip8.mode == Mode::XoChip;
+    match ins {
+        Instruction::Draw(_, _, n) => {
+            let bytes = if n == 0 && chip8.mode != Mode::Chip8 {
+                32
+            } else {
+                usize::from(n)
+            };
+            Some((
+                Access::Read,
+                at_i(bytes * chip8.planes.count_ones() as usize),
+            ))
+        }
+        Instruction::Bcd(_) => Some((Access::Write, at_i(3))),
+        Instruction::DumpRegs(x) => Some((Access::Write, at_i(usize::from(x) + 1))),
+        Instruction::LoadRegs(x) => Some((Access::Read, at_i(usize::from(x) + 1))),
+        Instruction::SaveRange(x, y) if xo => {
+            Some((Access::Write, at_i(usize::from(x.abs_diff(y)) + 1)))
+        }
+        Instruction::LoadRange(x, y) if xo => {
+            Some((Access::Read, at_i(usize::from(x.abs_diff(y)) + 1)))
+        }
+        Instruction::AudioPattern if xo => Some((Access::Read, at_i(16))),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Quirks, assemble};
+
+    const PROGRAM: &str = "
+            LD V0, 123      ; 0x200
+            CALL digits     ; 0x202
+            LD V1, 1        ; 0x204
+    halt:   JP halt         ; 0x206
+    digits: LD I, 0x300     ; 0x208
+            LD B, V0        ; 0x20A
+            DRW V2, V2, 3   ; 0x20C
+            RET             ; 0x20E
+    ";
+
+    fn chip8() -> Chip8 {
+        let mut chip8 = Chip8::new(Quirks::chip48());
+        chip8.load_rom(&assemble(PROGRAM).unwrap()).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        let mut chip8 = chip8();
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x20A);
+        assert_eq!(
+            debugger.run_frame(&mut chip8, 100).unwrap(),
+            Some(StopReason::Breakpoint { pc: 0x20A })
+        );
+        assert_eq!(chip8.memory[0x300], 0);
+        // Resuming runs the instruction at the breakpoint
+        debugger.run_frame(&mut chip8, 100).unwrap();
+        assert_eq!(chip8.memory[0x300..0x303], [1, 2, 3]);
+        assert_eq!(chip8.pc, 0x206);
+    }
+
+    #[test]
+    fn test_memory_watch() {
+        let mut chip8 = chip8();
+        let mut debugger = Debugger::new();
+        debugger.watch_memory(0x301, Watch::Write);
+        assert_eq!(
+            debugger.run_frame(&mut chip8, 100).unwrap(),
+            Some(StopReason::MemoryWrite {
+                pc: 0x20A,
+                addr: 0x301,
+                old: 0,
+                new: 2
+            })
+        );
+        debugger.unwatch_memory(0x301);
+        debugger.watch_memory(0x302, Watch::Read);
+        assert_eq!(
+            debugger.run_frame(&mut chip8, 100).unwrap(),
+            Some(StopReason::MemoryRead {
+                pc: 0x20C,
+                addr: 0x302
+            })
+        );
+    }
+
+    #[test]
+    fn test_register_watch() {
+        let mut chip8 = chip8();
+        let mut debugger = Debugger::new();
+        debugger.watch_register(Register::V(1));
+        assert_eq!(
+            debugger.run_frame(&mut chip8, 100).unwrap(),
+            Some(StopReason::RegisterChange {
+                pc: 0x204,
+                register: Register::V(1),
+                old: 0,
+                new: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_stepping() {
+        let mut chip8 = chip8();
+        let mut debugger = Debugger::new();
+        assert_eq!(debugger.step(&mut chip8).unwrap(), StopReason::Step);
+        assert_eq!(chip8.pc, 0x202);
+
+        // Stepping over the call runs the whole subroutine
+        assert_eq!(debugger.step_over(&mut chip8).unwrap(), None);
+        assert_eq!(
+            debugger.run_frame(&mut chip8, 100).unwrap(),
+            Some(StopReason::Step)
+        );
+        assert_eq!(chip8.pc, 0x204);
+        assert_eq!(chip8.memory[0x300..0x303], [1, 2, 3]);
+        assert_eq!(
+            debugger.step_over(&mut chip8).unwrap(),
+            Some(StopReason::Step)
+        );
+        assert_eq!(chip8.pc, 0x206);
+    }
+
+    #[test]
+    fn test_step_out() {
+        let mut chip8 = chip8();
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x20A);
+        debugger.run_frame(&mut chip8, 100).unwrap();
+        debugger.step_out(&chip8);
+        assert_eq!(
+            debugger.run_frame(&mut chip8, 100).unwrap(),
+            Some(StopReason::Step)
+        );
+        assert_eq!(chip8.pc, 0x204);
+    }
+}
@@ -0,0 +1,451 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::instruction::Instruction;
+
+/// Assembly syntax used when printing instructions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    /// Cowgod's mnemonics, e.g. `LD V1, 0x02`
+    Classic,
+    /// Octo's assignment style, e.g. `v1 := 0x02`
+    Octo,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineKind {
+    /// An instruction reachable from the entry point
+    Code(Instruction),
+    /// Bytes that are never executed
+    Data,
+}
+
+/// One line of a listing: an instruction or a run of data bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub kind: LineKind,
+}
+
+/// Result of disassembling a ROM, see [`disassemble`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listing {
+    pub origin: u16,
+    pub lines: Vec<Line>,
+    /// Generated names for the targets of jumps and calls
+    pub labels: BTreeMap<u16, String>,
+}
+
+/// Maximum number of bytes printed on one data line
+const DATA_LINE_BYTES: usize = 8;
+
+/// Disassembles `rom` as loaded at address `origin`.
+///
+/// Code is found by following every path from `origin`, through jumps, calls
+/// and both sides of skips. Bytes that are never reached are listed as data.
+pub fn disassemble(rom: &[u8], origin: u16) -> Listing {
+    let end = usize::from(origin) + rom.len();
+    let decode_at = |addr: usize| -> Option<Instruction> {
+        if addr < usize::from(origin) || addr + 1 >= end {
+            return None;
+        }
+        let offset = addr - usize::from(origin);
+        let ins = Instruction::from(u16::from_be_bytes([rom[offset], rom[offset + 1]]));
+        if ins == Instruction::NoOp || addr + usize::from(ins.size()) > end {
+            return None;
+        }
+        Some(ins)
+    };
+
+    let mut code = BTreeMap::new();
+    let mut jump_targets = BTreeSet::new();
+    let mut call_targets = BTreeSet::new();
+    let mut pending = vec![usize::from(origin)];
+    while let Some(addr) = pending.pop() {
+        if code.contains_key(&addr) {
+            continue;
+        }
+        let Some(ins) = decode_at(addr) else {
+            continue;
+        };
+        let next = addr + usize::from(ins.size());
+        match ins {
+            Instruction::Jump(target) => {
+                jump_targets.insert(target);
+                pending.push(usize::from(target));
+            }
+            Instruction::Call(target) => {
+                call_targets.insert(target);
+                pending.push(usize::from(target));
+                pending.push(next);
+            }
+            // The target of 0xBNNN depends on a register, so it can't be followed
+            Instruction::Ret | Instruction::Exit | Instruction::JumpV0(_) => {}
+            Instruction::SkipEqByte(..)
+            | Instruction::SkipNeByte(..)
+            | Instruction::SkipEqReg(..)
+            | Instruction::SkipNeReg(..)
+            | Instruction::SkipIfKey(_)
+            | Instruction::SkipIfNotKey(_) => {
+                pending.push(next);
+                let skipped = decode_at(next).map_or(2, |ins| ins.size());
+                pending.push(next + usize::from(skipped));
+            }
+            _ => pending.push(next),
+        }
+        code.insert(addr, ins);
+    }
+
+    let mut lines: Vec<Line> = Vec::new();
+    let mut addr = usize::from(origin);
+    while addr < end {
+        let offset = addr - usize::from(origin);
+        if let Some(ins) = code.get(&addr) {
+            let size = usize::from(ins.size());
+            lines.push(Line {
+                addr: addr as u16,
+                bytes: rom[offset..offset + size].to_vec(),
+                kind: LineKind::Code(*ins),
+            });
+            addr += size;
+            continue;
+        }
+        // Extend the previous data line unless it's full or this byte is a jump target
+        let is_target =
+            jump_targets.contains(&(addr as u16)) || call_targets.contains(&(addr as u16));
+        match lines.last_mut() {
+            Some(line)
+                if line.kind == LineKind::Data
+                    && line.bytes.len() < DATA_LINE_BYTES
+                    && !is_target =>
+            {
+                line.bytes.push(rom[offset]);
+            }
+            _ => lines.push(Line {
+                addr: addr as u16,
+                bytes: vec![rom[offset]],
+                kind: LineKind::Data,
+            }),
+        }
+        addr += 1;
+    }
+
+    // Only name targets that start a line, others can't be printed as labels
+    let starts: BTreeSet<u16> = lines.iter().map(|line| line.addr).collect();
+    let mut labels = BTreeMap::new();
+    for &target in jump_targets.iter().filter(|target| starts.contains(target)) {
+        labels.insert(target, format!("label_{:03X}", target));
+    }
+    for &target in call_targets.iter().filter(|target| starts.contains(target)) {
+        labels.insert(target, format!("sub_{:03X}", target));
+    }
+
+    Listing {
+        origin,
+        lines,
+        labels,
+    }
+}
+
+impl Listing {
+    /// Renders the listing with one line per instruction or data run,
+    /// preceded by any label pointing at it
+    pub fn render(&self, syntax: Syntax) -> String {
+        let mut out = String::new();
+        if syntax == Syntax::Octo && self.origin != 0x200 {
+            out.push_str(&format!(":org 0x{:03X}\n", self.origin));
+        }
+        for line in &self.lines {
+            if let Some(label) = self.labels.get(&line.addr) {
+                match syntax {
+                    Syntax::Classic => out.push_str(&format!("{}:\n", label)),
+                    Syntax::Octo => out.push_str(&format!(": {}\n", label)),
+                }
+            }
+            let text = match &line.kind {
+                LineKind::Code(ins) => {
+                    let long = (line.bytes.len() == 4)
+                        .then(|| u16::from_be_bytes([line.bytes[2], line.bytes[3]]));
+                    format_instruction(ins, syntax, long, &self.labels)
+                }
+                LineKind::Data => format_data(&line.bytes, syntax),
+            };
+            let hex: String = line.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            match syntax {
+                Syntax::Classic => {
+                    out.push_str(&format!("0x{:03X}  {:<16}  {}\n", line.addr, hex, text))
+                }
+                Syntax::Octo => {
+                    out.push_str(&format!("\t{:<24} # 0x{:03X}  {}\n", text, line.addr, hex))
+                }
+            }
+        }
+        out
+    }
+}
+
+fn format_data(bytes: &[u8], syntax: Syntax) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+    match syntax {
+        Syntax::Classic => format!("DB {}", bytes.join(", ")),
+        Syntax::Octo => bytes.join(" "),
+    }
+}
+
+fn format_instruction(
+    ins: &Instruction,
+    syntax: Syntax,
+    long: Option<u16>,
+    labels: &BTreeMap<u16, String>,
+) -> String {
+    let addr = |addr: &u16| {
+        labels
+            .get(addr)
+            .cloned()
+            .unwrap_or_else(|| format!("0x{:03X}", addr))
+    };
+    let long = long.map_or_else(|| "0x????".to_string(), |addr| format!("0x{:04X}", addr));
+    match syntax {
+        Syntax::Classic => match ins {
+            Instruction::Cls => "CLS".to_string(),
+            Instruction::Ret => "RET".to_string(),
+            Instruction::ScrollDown(n) => format!("SCD 0x{:X}", n),
+            Instruction::ScrollRight => "SCR".to_string(),
+            Instruction::ScrollLeft => "SCL".to_string(),
+            Instruction::Exit => "EXIT".to_string(),
+            Instruction::Lores => "LOW".to_string(),
+            Instruction::Hires => "HIGH".to_string(),
+            Instruction::Sys(nnn) => format!("SYS {}", addr(nnn)),
+            Instruction::Jump(nnn) => format!("JP {}", addr(nnn)),
+            Instruction::Call(nnn) => format!("CALL {}", addr(nnn)),
+            Instruction::SkipEqByte(x, nn) => format!("SE V{:X}, 0x{:02X}", x, nn),
+            Instruction::SkipNeByte(x, nn) => format!("SNE V{:X}, 0x{:02X}", x, nn),
+            Instruction::SkipEqReg(x, y) => format!("SE V{:X}, V{:X}", x, y),
+            Instruction::SaveRange(x, y) => format!("SAVE V{:X}, V{:X}", x, y),
+            Instruction::LoadRange(x, y) => format!("LOAD V{:X}, V{:X}", x, y),
+            Instruction::LoadByte(x, nn) => format!("LD V{:X}, 0x{:02X}", x, nn),
+            Instruction::AddByte(x, nn) => format!("ADD V{:X}, 0x{:02X}", x, nn),
+            Instruction::LoadReg(x, y) => format!("LD V{:X}, V{:X}", x, y),
+            Instruction::OrReg(x, y) => format!("OR V{:X}, V{:X}", x, y),
+            Instruction::AndReg(x, y) => format!("AND V{:X}, V{:X}", x, y),
+            Instruction::XorReg(x, y) => format!("XOR V{:X}, V{:X}", x, y),
+            Instruction::AddReg(x, y) => format!("ADD V{:X}, V{:X}", x, y),
+            Instruction::SubReg(x, y) => format!("SUB V{:X}, V{:X}", x, y),
+            Instruction::ShrReg(x, y) => format!("SHR V{:X}, V{:X}", x, y),
+            Instruction::SubnReg(x, y) => format!("SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShlReg(x, y) => format!("SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipNeReg(x, y) => format!("SNE V{:X}, V{:X}", x, y),
+            Instruction::LoadI(nnn) => format!("LD I, {}", addr(nnn)),
+            Instruction::JumpV0(nnn) => format!("JP V0, {}", addr(nnn)),
+            Instruction::Rand(x, nn) => format!("RND V{:X}, 0x{:02X}", x, nn),
+            Instruction::Draw(x, y, n) => format!("DRW V{:X}, V{:X}, 0x{:X}", x, y, n),
+            Instruction::SkipIfKey(x) => format!("SKP V{:X}", x),
+            Instruction::SkipIfNotKey(x) => format!("SKNP V{:X}", x),
+            Instruction::LoadILong => format!("LD I, LONG {}", long),
+            Instruction::Plane(n) => format!("PLANE 0x{:X}", n),
+            Instruction::AudioPattern => "AUDIO".to_string(),
+            Instruction::LoadDT(x) => format!("LD V{:X}, DT", x),
+            Instruction::WaitKey(x) => format!("LD V{:X}, K", x),
+            Instruction::SetDT(x) => format!("LD DT, V{:X}", x),
+            Instruction::SetST(x) => format!("LD ST, V{:X}", x),
+            Instruction::AddI(x) => format!("ADD I, V{:X}", x),
+            Instruction::LoadSprite(x) => format!("LD F, V{:X}", x),
+            Instruction::LoadBigSprite(x) => format!("LD HF, V{:X}", x),
+            Instruction::Pitch(x) => format!("PITCH V{:X}", x),
+            Instruction::Bcd(x) => format!("LD B, V{:X}", x),
+            Instruction::DumpRegs(x) => format!("LD [I], V{:X}", x),
+            Instruction::LoadRegs(x) => format!("LD V{:X}, [I]", x),
+            Instruction::StoreFlags(x) => format!("LD R, V{:X}", x),
+            Instruction::LoadFlags(x) => format!("LD V{:X}, R", x),
+            Instruction::NoOp => "???".to_string(),
+        },
+        Syntax::Octo => match ins {
+            Instruction::Cls => "clear".to_string(),
+            Instruction::Ret => "return".to_string(),
+            Instruction::ScrollDown(n) => format!("scroll-down 0x{:X}", n),
+            Instruction::ScrollRight => "scroll-right".to_string(),
+            Instruction::ScrollLeft => "scroll-left".to_string(),
+            Instruction::Exit => "exit".to_string(),
+            Instruction::Lores => "lores".to_string(),
+            Instruction::Hires => "hires".to_string(),
+            // Octo has no mnemonic for machine code calls
+            Instruction::Sys(nnn) => format!("0x{:02X} 0x{:02X}", nnn >> 8, nnn & 0xFF),
+            Instruction::Jump(nnn) => format!("jump {}", addr(nnn)),
+            Instruction::Call(nnn) => match labels.get(nnn) {
+                Some(label) => label.clone(),
+                None => format!(":call 0x{:03X}", nnn),
+            },
+            // Octo conditions say when the next instruction runs, skips say when it doesn't
+            Instruction::SkipEqByte(x, nn) => format!("if v{:x} != 0x{:02X} then", x, nn),
+            Instruction::SkipNeByte(x, nn) => format!("if v{:x} == 0x{:02X} then", x, nn),
+            Instruction::SkipEqReg(x, y) => format!("if v{:x} != v{:x} then", x, y),
+            Instruction::SaveRange(x, y) => format!("save v{:x} - v{:x}", x, y),
+            Instruction::LoadRange(x, y) => format!("load v{:x} - v{:x}", x, y),
+            Instruction::LoadByte(x, nn) => format!("v{:x} := 0x{:02X}", x, nn),
+            Instruction::AddByte(x, nn) => format!("v{:x} += 0x{:02X}", x, nn),
+            Instruction::LoadReg(x, y) => format!("v{:x} := v{:x}", x, y),
+            Instruction::OrReg(x, y) => format!("v{:x} |= v{:x}", x, y),
+            Instruction::AndReg(x, y) => format!("v{:x} &= v{:x}", x, y),
+            Instruction::XorReg(x, y) => format!("v{:x} ^= v{:x}", x, y),
+            Instruction::AddReg(x, y) => format!("v{:x} += v{:x}", x, y),
+            Instruction::SubReg(x, y) => format!("v{:x} -= v{:x}", x, y),
+            Instruction::ShrReg(x, y) => format!("v{:x} >>= v{:x}", x, y),
+            Instruction::SubnReg(x, y) => format!("v{:x} =- v{:x}", x, y),
+            Instruction::ShlReg(x, y) => format!("v{:x} <<= v{:x}", x, y),
+            Instruction::SkipNeReg(x, y) => format!("if v{:x} == v{:x} then", x, y),
+            Instruction::LoadI(nnn) => format!("i := {}", addr(nnn)),
+            Instruction::JumpV0(nnn) => format!("jump0 {}", addr(nnn)),
+            Instruction::Rand(x, nn) => format!("v{:x} := random 0x{:02X}", x, nn),
+            Instruction::Draw(x, y, n) => format!("sprite v{:x} v{:x} 0x{:X}", x, y, n),
+            Instruction::SkipIfKey(x) => format!("if v{:x} -key then", x),
+            Instruction::SkipIfNotKey(x) => format!("if v{:x} key then", x),
+            Instruction::LoadILong => format!("i := long {}", long),
+            Instruction::Plane(n) => format!("plane {}", n),
+            Instruction::AudioPattern => "audio".to_string(),
+            Instruction::LoadDT(x) => format!("v{:x} := delay", x),
+            Instruction::WaitKey(x) => format!("v{:x} := key", x),
+            Instruction::SetDT(x) => format!("delay := v{:x}", x),
+            Instruction::SetST(x) => format!("buzzer := v{:x}", x),
+            Instruction::AddI(x) => format!("i += v{:x}", x),
+            Instruction::LoadSprite(x) => format!("i := hex v{:x}", x),
+            Instruction::LoadBigSprite(x) => format!("i := bighex v{:x}", x),
+            Instruction::Pitch(x) => format!("pitch := v{:x}", x),
+            Instruction::Bcd(x) => format!("bcd v{:x}", x),
+            Instruction::DumpRegs(x) => format!("save v{:x}", x),
+            Instruction::LoadRegs(x) => format!("load v{:x}", x),
+            Instruction::StoreFlags(x) => format!("saveflags v{:x}", x),
+            Instruction::LoadFlags(x) => format!("loadflags v{:x}", x),
+            Instruction::NoOp => "# ???".to_string(),
+        },
+    }
+}
+
+impl Instruction {
+    /// The instruction as assembly in the given syntax. The operand of
+    /// 0xF000 NNNN is not part of the instruction and prints as `0x????`.
+    pub fn mnemonic(&self, syntax: Syntax) -> String {
+        format_instruction(self, syntax, None, &BTreeMap::new())
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.mnemonic(Syntax::Classic))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0x200: LD V1, 0x02
+    // 0x202: CALL 0x20A
+    // 0x204: SE V1, 0x02
+    // 0x206: JP 0x202
+    // 0x208: data, never reached
+    // 0x20A: LD I, 0x20E; RET
+    // 0x20E: sprite data
+    const ROM: [u8; 16] = [
+        0x61, 0x02, 0x22, 0x0A, 0x31, 0x02, 0x12, 0x02, 0xFF, 0xFF, 0xA2, 0x0E, 0x00, 0xEE, 0xF0,
+        0x90,
+    ];
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Instruction::LoadByte(0x1, 0x02).to_string(), "LD V1, 0x02");
+        assert_eq!(
+            Instruction::Draw(0xA, 0xB, 0x5).to_string(),
+            "DRW VA, VB, 0x5"
+        );
+        assert_eq!(
+            Instruction::LoadByte(0x1, 0x02).mnemonic(Syntax::Octo),
+            "v1 := 0x02"
+        );
+        assert_eq!(
+            Instruction::SkipEqByte(0xF, 0x00).mnemonic(Syntax::Octo),
+            "if vf != 0x00 then"
+        );
+    }
+
+    #[test]
+    fn test_reachability() {
+        let listing = disassemble(&ROM, 0x200);
+        let kinds: Vec<(u16, bool)> = listing
+            .lines
+            .iter()
+            .map(|line| (line.addr, matches!(line.kind, LineKind::Code(_))))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (0x200, true),
+                (0x202, true),
+                (0x204, true),
+                (0x206, true),
+                (0x208, false),
+                (0x20A, true),
+                (0x20C, true),
+                (0x20E, false),
+            ]
+        );
+        assert_eq!(listing.labels.get(&0x202).unwrap(), "label_202");
+        assert_eq!(listing.labels.get(&0x20A).unwrap(), "sub_20A");
+    }
+
+    #[test]
+    fn test_render_classic() {
+        let listing = disassemble(&ROM, 0x200);
+        assert_eq!(
+            listing.render(Syntax::Classic),
+            "\
+0x200  6102              LD V1, 0x02
+label_202:
+0x202  220A              CALL sub_20A
+0x204  3102              SE V1, 0x02
+0x206  1202              JP label_202
+0x208  FFFF              DB 0xFF, 0xFF
+sub_20A:
+0x20A  A20E              LD I, 0x20E
+0x20C  00EE              RET
+0x20E  F090              DB 0xF0, 0x90
+"
+        );
+    }
+
+    #[test]
+    fn test_render_octo() {
+        let listing = disassemble(&ROM, 0x200);
+        assert_eq!(
+            listing.render(Syntax::Octo),
+            "\
+\tv1 := 0x02               # 0x200  6102
+: label_202
+\tsub_20A                  # 0x202  220A
+\tif v1 != 0x02 then       # 0x204  3102
+\tjump label_202           # 0x206  1202
+\t0xFF 0xFF                # 0x208  FFFF
+: sub_20A
+\ti := 0x20E               # 0x20A  A20E
+\treturn                   # 0x20C  00EE
+\t0xF0 0x90                # 0x20E  F090
+"
+        );
+    }
+
+    #[test]
+    fn test_long_load() {
+        // LD I, LONG 0x1234; SE V0, 0x00; LD I, LONG 0xABCD; EXIT
+        let rom = [
+            0xF0, 0x00, 0x12, 0x34, 0x30, 0x00, 0xF0, 0x00, 0xAB, 0xCD, 0x00, 0xFD,
+        ];
+        let listing = disassemble(&rom, 0x200);
+        let addrs: Vec<u16> = listing.lines.iter().map(|line| line.addr).collect();
+        assert_eq!(addrs, vec![0x200, 0x204, 0x206, 0x20A]);
+        assert!(
+            listing
+                .render(Syntax::Classic)
+                .contains("LD I, LONG 0xABCD")
+        );
+    }
+}
@@ -0,0 +1,38 @@
+use std::fmt;
+
+/// Errors raised while loading or executing a program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chip8Error {
+    /// The ROM does not fit in memory above 0x200
+    RomTooLarge { size: usize, max: usize },
+    /// A 0x2NNN call was made with all 16 stack entries in use
+    StackOverflow { pc: u16 },
+    /// A 0x00EE return was made with an empty stack
+    StackUnderflow { pc: u16 },
+    /// The instruction at `pc` accessed memory past the end of the address space
+    MemoryOutOfBounds { addr: usize, pc: u16 },
+    /// The opcode at `pc` is not part of the instruction set
+    InvalidOpcode { opcode: u16, pc: u16 },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::RomTooLarge { size, max } => {
+                write!(f, "ROM is {} bytes, at most {} fit in memory", size, max)
+            }
+            Chip8Error::StackOverflow { pc } => write!(f, "stack overflow at {:#06x}", pc),
+            Chip8Error::StackUnderflow { pc } => write!(f, "stack underflow at {:#06x}", pc),
+            Chip8Error::MemoryOutOfBounds { addr, pc } => write!(
+                f,
+                "memory access out of bounds at {:#06x} by instruction at {:#06x}",
+                addr, pc
+            ),
+            Chip8Error::InvalidOpcode { opcode, pc } => {
+                write!(f, "invalid opcode {:#06x} at {:#06x}", opcode, pc)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {}
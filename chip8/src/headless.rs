@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::{fs, io};
+
+use crate::{Chip8, Chip8Error, Screenshot};
+
+/// Runs an interpreter without a display, frame by frame, feeding it
+/// scripted key presses
+#[derive(Debug)]
+pub struct Headless {
+    chip8: Chip8,
+    pub instructions_per_frame: u32,
+    /// Whether the timers count down once per frame. When off, frames still
+    /// end the display wait but the timers keep their values.
+    pub step_timers: bool,
+    frame: u32,
+    /// Key changes by the frame before which they happen
+    script: BTreeMap<u32, Vec<(u8, bool)>>,
+}
+
+impl Headless {
+    pub fn new(chip8: Chip8) -> Self {
+        Headless {
+            chip8,
+            instructions_per_frame: 10,
+            step_timers: true,
+            frame: 0,
+            script: BTreeMap::new(),
+        }
+    }
+
+    /// Presses or releases `key` before frame `frame` runs
+    pub fn key_at(&mut self, frame: u32, key: u8, pressed: bool) {
+        self.script.entry(frame).or_default().push((key, pressed));
+    }
+
+    /// Holds `key` from frame `from` until frame `until`
+    pub fn hold(&mut self, key: u8, from: u32, until: u32) {
+        self.key_at(from, key, true);
+        self.key_at(until, key, false);
+    }
+
+    /// Runs `frames` frames, applying the scripted keys as their frames come up
+    pub fn run(&mut self, frames: u32) -> Result<(), Chip8Error> {
+        for _ in 0..frames {
+            if let Some(keys) = self.script.remove(&self.frame) {
+                for (key, pressed) in keys {
+                    self.chip8.keypress(usize::from(key), pressed);
+                }
+            }
+            if self.step_timers {
+                self.chip8.run_frame(self.instructions_per_frame)?;
+            } else {
+                for _ in 0..self.instructions_per_frame {
+                    if self.chip8.waiting_vblank {
+                        break;
+                    }
+                    self.chip8.tick()?;
+                }
+                self.chip8.waiting_vblank = false;
+            }
+            self.frame += 1;
+        }
+        Ok(())
+    }
+
+    /// Number of frames run so far
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn chip8(&self) -> &Chip8 {
+        &self.chip8
+    }
+
+    pub fn chip8_mut(&mut self) -> &mut Chip8 {
+        &mut self.chip8
+    }
+
+    pub fn into_chip8(self) -> Chip8 {
+        self.chip8
+    }
+
+    /// The display as a plain PBM image, see [`Screenshot::write_pbm`]
+    pub fn screen_pbm(&self) -> String {
+        let mut pbm = Vec::new();
+        Screenshot::capture(&self.chip8)
+            .write_pbm(&mut pbm)
+            .expect("writing to a Vec does not fail");
+        String::from_utf8(pbm).expect("PBM is ASCII")
+    }
+
+    /// Compares the display with a reference image written by
+    /// [`Headless::screen_pbm`]
+    pub fn compare_golden(&self, path: impl AsRef<Path>) -> Result<(), GoldenError> {
+        let golden = fs::read_to_string(path).map_err(GoldenError::Io)?;
+        let (expected_size, expected) = parse_pbm(&golden).ok_or(GoldenError::Malformed)?;
+        let actual_pbm = self.screen_pbm();
+        let (actual_size, actual) = parse_pbm(&actual_pbm).expect("screen_pbm output parses");
+        if expected_size != actual_size {
+            return Err(GoldenError::SizeMismatch {
+                expected: expected_size,
+                actual: actual_size,
+            });
+        }
+        let pixels = expected
+            .iter()
+            .zip(&actual)
+            .filter(|(expected, actual)| expected != actual)
+            .count();
+        if pixels != 0 {
+            return Err(GoldenError::Mismatch {
+                pixels,
+                actual: actual_pbm,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum GoldenError {
+    /// The reference image could not be read
+    Io(io::Error),
+    /// The reference image is not a plain PBM file
+    Malformed,
+    /// The reference image and the display differ in size, as (width, height)
+    SizeMismatch {
+        expected: (usize, usize),
+        actual: (usize, usize),
+    },
+    /// `pixels` pixels differ. `actual` is the display as PBM.
+    Mismatch { pixels: usize, actual: String },
+}
+
+impl fmt::Display for GoldenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GoldenError::Io(err) => write!(f, "failed to read reference image: {}", err),
+            GoldenError::Malformed => write!(f, "reference image is not a plain PBM file"),
+            GoldenError::SizeMismatch { expected, actual } => write!(
+                f,
+                "display is {}x{} but the reference image is {}x{}",
+                actual.0, actual.1, expected.0, expected.1
+            ),
+            GoldenError::Mismatch { pixels, actual } => {
+                write!(
+                    f,
+                    "{} pixels differ from the reference image, got:\n{}",
+                    pixels, actual
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for GoldenError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GoldenError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Reads a plain PBM image into its size and one bool per pixel
+fn parse_pbm(text: &str) -> Option<((usize, usize), Vec<bool>)> {
+    // Comments run from '#' to the end of the line
+    let mut tokens = text
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .flat_map(str::split_whitespace);
+    if tokens.next()? != "P1" {
+        return None;
+    }
+    let width = tokens.next()?.parse().ok()?;
+    let height = tokens.next()?.parse().ok()?;
+    let pixels: Vec<bool> = tokens
+        .flat_map(str::chars)
+        .map(|c| match c {
+            '0' => Some(false),
+            '1' => Some(true),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+    (pixels.len() == width * height).then_some(((width, height), pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Quirks;
+    use std::env;
+    use std::path::PathBuf;
+
+    /// Checks the display against `tests/golden/<name>.pbm`. Set
+    /// `UPDATE_GOLDEN` to rewrite the reference images instead.
+    fn check_golden(runner: &Headless, name: &str) {
+        let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "golden", name]
+            .iter()
+            .collect::<PathBuf>()
+            .with_extension("pbm");
+        if env::var_os("UPDATE_GOLDEN").is_some() {
+            fs::write(&path, runner.screen_pbm()).unwrap();
+        }
+        if let Err(err) = runner.compare_golden(&path) {
+            panic!("{}: {}", name, err);
+        }
+    }
+
+    /// Runs `examples/timendus/<name>.ch8` with the VIP quirks
+    fn run_test_rom(name: &str, frames: u32) -> Headless {
+        let path: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "..",
+            "examples",
+            "timendus",
+            name,
+        ]
+        .iter()
+        .collect::<PathBuf>()
+        .with_extension("ch8");
+        let mut chip8 = Chip8::new(Quirks::cosmac_vip());
+        chip8.load_rom(&fs::read(path).unwrap()).unwrap();
+        let mut runner = Headless::new(chip8);
+        runner.instructions_per_frame = 20;
+        runner.run(frames).unwrap();
+        runner
+    }
+
+    #[test]
+    fn test_scripted_keys() {
+        // LD V0, 0x05; LD DT, V0; LD V1, K; JP 0x206
+        let mut chip8 = Chip8::new(Quirks::cosmac_vip());
+        chip8
+            .load_rom(&[0x60, 0x05, 0xF0, 0x15, 0xF1, 0x0A, 0x12, 0x06])
+            .unwrap();
+        let mut runner = Headless::new(chip8);
+        runner.step_timers = false;
+        runner.hold(0x9, 2, 4);
+        runner.run(3).unwrap();
+        assert_eq!(runner.chip8().state().pc, 0x204);
+        runner.run(2).unwrap();
+        assert_eq!(runner.frame(), 5);
+        let state = runner.chip8().state();
+        assert_eq!(state.pc, 0x206);
+        assert_eq!(state.v[1], 0x9);
+        assert_eq!(state.delay_timer, 5);
+    }
+
+    #[test]
+    fn test_golden_mismatch() {
+        let runner = run_test_rom("2-ibm-logo", 60);
+        let path: PathBuf = [
+            env!("CARGO_MANIFEST_DIR"),
+            "tests",
+            "golden",
+            "1-chip8-logo.pbm",
+        ]
+        .iter()
+        .collect();
+        assert!(matches!(
+            runner.compare_golden(path),
+            Err(GoldenError::Mismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_chip8_logo() {
+        check_golden(&run_test_rom("1-chip8-logo", 60), "1-chip8-logo");
+    }
+
+    #[test]
+    fn test_ibm_logo() {
+        check_golden(&run_test_rom("2-ibm-logo", 60), "2-ibm-logo");
+    }
+
+    #[test]
+    fn test_corax_plus() {
+        check_golden(&run_test_rom("3-corax+", 120), "3-corax+");
+    }
+
+    #[test]
+    fn test_flags() {
+        check_golden(&run_test_rom("4-flags", 120), "4-flags");
+    }
+}
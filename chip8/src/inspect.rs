@@ -0,0 +1,147 @@
+use crate::{Chip8, Chip8Error, Mode, Quirks, Register};
+
+/// Read-only view of the interpreter, see [`Chip8::state`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chip8State<'a> {
+    pub v: &'a [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    /// Return addresses of the active calls, innermost last
+    pub stack: &'a [u16],
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub memory: &'a [u8],
+    pub keypad: &'a [u8; 16],
+    pub rpl_flags: &'a [u8; 16],
+    pub mode: Mode,
+    pub quirks: Quirks,
+    pub hires: bool,
+    /// Bitplanes selected by 0xFN01
+    pub planes: u8,
+    /// Whether execution is paused until the next frame by the display wait quirk
+    pub waiting_vblank: bool,
+    /// Whether 0xFX0A is waiting for a key
+    pub waiting_key: bool,
+    pub halted: bool,
+}
+
+impl Chip8 {
+    /// A snapshot of the registers, stack, timers and memory
+    pub fn state(&self) -> Chip8State<'_> {
+        Chip8State {
+            v: &self.v,
+            i: self.i,
+            pc: self.pc,
+            stack: &self.stack[..usize::from(self.sp)],
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            memory: &self.memory,
+            keypad: &self.keypad,
+            rpl_flags: &self.rpl_flags,
+            mode: self.mode,
+            quirks: self.quirks,
+            hires: self.hires,
+            planes: self.planes,
+            waiting_vblank: self.waiting_vblank,
+            waiting_key: self.waiting_key,
+            halted: self.halted,
+        }
+    }
+
+    /// The byte at `addr`, or `None` past the end of memory
+    pub fn peek(&self, addr: u16) -> Option<u8> {
+        self.memory.get(usize::from(addr)).copied()
+    }
+
+    /// Overwrites the byte at `addr`. Fails with
+    /// [`Chip8Error::MemoryOutOfBounds`] past the end of memory, blaming the
+    /// current PC.
+    pub fn poke(&mut self, addr: u16, value: u8) -> Result<(), Chip8Error> {
+        match self.memory.get_mut(usize::from(addr)) {
+            Some(byte) => {
+                *byte = value;
+                let addr = usize::from(addr);
+                self.invalidate_decoded(addr..addr + 1);
+                Ok(())
+            }
+            None => Err(Chip8Error::MemoryOutOfBounds {
+                addr: usize::from(addr),
+                pc: self.pc,
+            }),
+        }
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// Value of a register. V registers and timers are widened from a byte.
+    pub fn register(&self, register: Register) -> u16 {
+        match register {
+            Register::V(x) => u16::from(self.v[usize::from(x & 0xF)]),
+            Register::I => self.i,
+            Register::DelayTimer => u16::from(self.delay_timer),
+            Register::SoundTimer => u16::from(self.sound_timer),
+        }
+    }
+
+    /// Sets a register. V registers and timers keep the low byte of `value`.
+    pub fn set_register(&mut self, register: Register, value: u16) {
+        match register {
+            Register::V(x) => self.v[usize::from(x & 0xF)] = value as u8,
+            Register::I => self.i = value,
+            Register::DelayTimer => self.delay_timer = value as u8,
+            Register::SoundTimer => self.sound_timer = value as u8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poke_then_execute() {
+        let mut chip8 = Chip8::default();
+        // LD V0, [I] at 0x400, loading from 0x300
+        chip8.poke(0x400, 0xF0).unwrap();
+        chip8.poke(0x401, 0x65).unwrap();
+        chip8.poke(0x300, 0x42).unwrap();
+        chip8.set_pc(0x400);
+        chip8.set_register(Register::I, 0x300);
+        chip8.set_register(Register::DelayTimer, 5);
+        chip8.tick().unwrap();
+
+        let state = chip8.state();
+        assert_eq!(state.v[0], 0x42);
+        assert_eq!(state.pc, 0x402);
+        assert_eq!(state.delay_timer, 5);
+        assert_eq!(chip8.peek(0x401), Some(0x65));
+        assert_eq!(chip8.register(Register::V(0)), 0x42);
+    }
+
+    #[test]
+    fn test_out_of_bounds() {
+        let mut chip8 = Chip8::default();
+        assert_eq!(chip8.peek(0x1000), None);
+        assert_eq!(
+            chip8.poke(0x1000, 1),
+            Err(Chip8Error::MemoryOutOfBounds {
+                addr: 0x1000,
+                pc: 0x200
+            })
+        );
+    }
+
+    #[test]
+    fn test_stack_view() {
+        // CALL 0x204; NOP padding; CALL 0x208
+        let mut chip8 = Chip8::default();
+        chip8
+            .load_rom(&[0x22, 0x04, 0x00, 0x00, 0x22, 0x08])
+            .unwrap();
+        chip8.tick().unwrap();
+        chip8.tick().unwrap();
+        assert_eq!(chip8.state().stack, &[0x202, 0x206]);
+    }
+}
@@ -1,9 +1,23 @@
-#[derive(Debug, PartialEq, Eq)]
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
     /// 0x00E0 - Clear the display
     Cls,
     /// 0x00EE - Return from a subroutine
     Ret,
+    /// 0x00CN - Scroll the display down by N pixels (SUPER-CHIP)
+    ScrollDown(u8),
+    /// 0x00FB - Scroll the display right by 4 pixels (SUPER-CHIP)
+    ScrollRight,
+    /// 0x00FC - Scroll the display left by 4 pixels (SUPER-CHIP)
+    ScrollLeft,
+    /// 0x00FD - Exit the interpreter (SUPER-CHIP)
+    Exit,
+    /// 0x00FE - Switch to 64x32 low resolution mode (SUPER-CHIP)
+    Lores,
+    /// 0x00FF - Switch to 128x64 high resolution mode (SUPER-CHIP)
+    Hires,
     /// 0x0NNN - Jump to a machine code routine at address NNN
     Sys(u16),
     /// 0x1NNN - Jump to address NNN
@@ -16,6 +30,10 @@ pub enum Instruction {
     SkipNeByte(u8, u8),
     /// 0x5XY0 - Skip next instruction if VX equals VY
     SkipEqReg(u8, u8),
+    /// 0x5XY2 - Store registers VX through VY in memory starting at address I (XO-CHIP)
+    SaveRange(u8, u8),
+    /// 0x5XY3 - Read registers VX through VY from memory starting at address I (XO-CHIP)
+    LoadRange(u8, u8),
     /// 0x6XNN - Set VX to NN
     LoadByte(u8, u8),
     /// 0x7XNN - Add NN to VX
@@ -46,12 +64,19 @@ pub enum Instruction {
     JumpV0(u16),
     /// 0xCXNN - Set VX to a random byte AND NN
     Rand(u8, u8),
-    /// 0xDXYN - Draw a sprite at (VX, VY) with N bytes of sprite data starting at the address stored in I
+    /// 0xDXYN - Draw a sprite at (VX, VY) with N bytes of sprite data starting at the address stored in I.
+    /// On SUPER-CHIP, N = 0 draws a 16x16 sprite
     Draw(u8, u8, u8),
     /// 0xEX9E - Skip next instruction if key with the value of VX is pressed
     SkipIfKey(u8),
     /// 0xEXA1 - Skip next instruction if key with the value of VX is not pressed
     SkipIfNotKey(u8),
+    /// 0xF000 NNNN - Set I to the 16-bit address NNNN stored in the following word (XO-CHIP)
+    LoadILong,
+    /// 0xFN01 - Select the bitplanes N that drawing, clearing and scrolling apply to (XO-CHIP)
+    Plane(u8),
+    /// 0xF002 - Load the 16 byte audio pattern buffer from memory starting at address I (XO-CHIP)
+    AudioPattern,
     /// 0xFX07 - Set VX to the value of the delay timer
     LoadDT(u8),
     /// 0xFX0A - Wait for a key press and store the value of the key in VX
@@ -64,12 +89,20 @@ pub enum Instruction {
     AddI(u8),
     /// 0xFX29 - Set I to the location of the sprite for the character in VX
     LoadSprite(u8),
+    /// 0xFX30 - Set I to the location of the large sprite for the digit in VX (SUPER-CHIP)
+    LoadBigSprite(u8),
+    /// 0xFX3A - Set the audio pattern playback pitch to VX (XO-CHIP)
+    Pitch(u8),
     /// 0xFX33 - Store the binary-coded decimal representation of VX at addresses I, I+1, and I+2
     Bcd(u8),
     /// 0xFX55 - Store registers V0 through VX in memory starting at address I
     DumpRegs(u8),
     /// 0xFX65 - Read registers V0 through VX from memory starting at address I
     LoadRegs(u8),
+    /// 0xFX75 - Store registers V0 through VX in the RPL user flags (SUPER-CHIP)
+    StoreFlags(u8),
+    /// 0xFX85 - Read registers V0 through VX from the RPL user flags (SUPER-CHIP)
+    LoadFlags(u8),
     /// No operation (invalid or unrecognized opcode)
     NoOp,
 }
@@ -79,6 +112,12 @@ impl From<u16> for Instruction {
         match opcode {
             0x00E0 => Instruction::Cls,
             0x00EE => Instruction::Ret,
+            0x00FB => Instruction::ScrollRight,
+            0x00FC => Instruction::ScrollLeft,
+            0x00FD => Instruction::Exit,
+            0x00FE => Instruction::Lores,
+            0x00FF => Instruction::Hires,
+            _ if opcode & 0xFFF0 == 0x00C0 => Instruction::ScrollDown((opcode & 0x000F) as u8),
             _ if opcode & 0xF000 == 0x0000 => Instruction::Sys(opcode & 0x0FFF),
             _ if opcode & 0xF000 == 0x1000 => Instruction::Jump(opcode & 0x0FFF),
             _ if opcode & 0xF000 == 0x2000 => Instruction::Call(opcode & 0x0FFF),
@@ -92,6 +131,14 @@ impl From<u16> for Instruction {
                 ((opcode & 0x0F00) >> 8) as u8,
                 ((opcode & 0x00F0) >> 4) as u8,
             ),
+            _ if opcode & 0xF00F == 0x5002 => Instruction::SaveRange(
+                ((opcode & 0x0F00) >> 8) as u8,
+                ((opcode & 0x00F0) >> 4) as u8,
+            ),
+            _ if opcode & 0xF00F == 0x5003 => Instruction::LoadRange(
+                ((opcode & 0x0F00) >> 8) as u8,
+                ((opcode & 0x00F0) >> 4) as u8,
+            ),
             _ if opcode & 0xF000 == 0x6000 => {
                 Instruction::LoadByte(((opcode & 0x0F00) >> 8) as u8, (opcode & 0x00FF) as u8)
             }
@@ -154,6 +201,9 @@ impl From<u16> for Instruction {
             _ if opcode & 0xF0FF == 0xE0A1 => {
                 Instruction::SkipIfNotKey(((opcode & 0x0F00) >> 8) as u8)
             }
+            0xF000 => Instruction::LoadILong,
+            0xF002 => Instruction::AudioPattern,
+            _ if opcode & 0xF0FF == 0xF001 => Instruction::Plane(((opcode & 0x0F00) >> 8) as u8),
             _ if opcode & 0xF0FF == 0xF007 => Instruction::LoadDT(((opcode & 0x0F00) >> 8) as u8),
             _ if opcode & 0xF0FF == 0xF00A => Instruction::WaitKey(((opcode & 0x0F00) >> 8) as u8),
             _ if opcode & 0xF0FF == 0xF015 => Instruction::SetDT(((opcode & 0x0F00) >> 8) as u8),
@@ -162,10 +212,160 @@ impl From<u16> for Instruction {
             _ if opcode & 0xF0FF == 0xF029 => {
                 Instruction::LoadSprite(((opcode & 0x0F00) >> 8) as u8)
             }
+            _ if opcode & 0xF0FF == 0xF030 => {
+                Instruction::LoadBigSprite(((opcode & 0x0F00) >> 8) as u8)
+            }
+            _ if opcode & 0xF0FF == 0xF03A => Instruction::Pitch(((opcode & 0x0F00) >> 8) as u8),
             _ if opcode & 0xF0FF == 0xF033 => Instruction::Bcd(((opcode & 0x0F00) >> 8) as u8),
             _ if opcode & 0xF0FF == 0xF055 => Instruction::DumpRegs(((opcode & 0x0F00) >> 8) as u8),
             _ if opcode & 0xF0FF == 0xF065 => Instruction::LoadRegs(((opcode & 0x0F00) >> 8) as u8),
+            _ if opcode & 0xF0FF == 0xF075 => {
+                Instruction::StoreFlags(((opcode & 0x0F00) >> 8) as u8)
+            }
+            _ if opcode & 0xF0FF == 0xF085 => {
+                Instruction::LoadFlags(((opcode & 0x0F00) >> 8) as u8)
+            }
             _ => Instruction::NoOp,
         }
     }
 }
+
+impl Instruction {
+    /// Size in bytes of the instruction, including any operand words that follow the opcode
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LoadILong => 4,
+            _ => 2,
+        }
+    }
+}
+
+/// Reasons an [`Instruction`] can't be encoded as an opcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodeError {
+    /// An operand is wider than its field in the opcode
+    OperandOutOfRange(Instruction),
+    /// `NoOp` stands in for every unrecognized opcode, so it has no encoding
+    NoOp,
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::OperandOutOfRange(ins) => write!(f, "operand out of range in {:?}", ins),
+            EncodeError::NoOp => write!(f, "NoOp has no opcode"),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+impl TryFrom<Instruction> for u16 {
+    type Error = EncodeError;
+
+    /// Encodes the instruction as its opcode. For 0xF000 NNNN only the first
+    /// word is produced, the address follows it as a separate word.
+    fn try_from(ins: Instruction) -> Result<Self, Self::Error> {
+        let out_of_range = EncodeError::OperandOutOfRange(ins);
+        let nnn = |addr: u16| (addr <= 0x0FFF).then_some(addr).ok_or(out_of_range);
+        let x = |reg: u8| {
+            (reg <= 0xF)
+                .then_some(u16::from(reg) << 8)
+                .ok_or(out_of_range)
+        };
+        let y = |reg: u8| {
+            (reg <= 0xF)
+                .then_some(u16::from(reg) << 4)
+                .ok_or(out_of_range)
+        };
+        let n = |nibble: u8| {
+            (nibble <= 0xF)
+                .then_some(u16::from(nibble))
+                .ok_or(out_of_range)
+        };
+        let opcode = match ins {
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::ScrollDown(rows) => 0x00C0 | n(rows)?,
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::Lores => 0x00FE,
+            Instruction::Hires => 0x00FF,
+            Instruction::Sys(addr) => nnn(addr)?,
+            Instruction::Jump(addr) => 0x1000 | nnn(addr)?,
+            Instruction::Call(addr) => 0x2000 | nnn(addr)?,
+            Instruction::SkipEqByte(vx, byte) => 0x3000 | x(vx)? | u16::from(byte),
+            Instruction::SkipNeByte(vx, byte) => 0x4000 | x(vx)? | u16::from(byte),
+            Instruction::SkipEqReg(vx, vy) => 0x5000 | x(vx)? | y(vy)?,
+            Instruction::SaveRange(vx, vy) => 0x5002 | x(vx)? | y(vy)?,
+            Instruction::LoadRange(vx, vy) => 0x5003 | x(vx)? | y(vy)?,
+            Instruction::LoadByte(vx, byte) => 0x6000 | x(vx)? | u16::from(byte),
+            Instruction::AddByte(vx, byte) => 0x7000 | x(vx)? | u16::from(byte),
+            Instruction::LoadReg(vx, vy) => 0x8000 | x(vx)? | y(vy)?,
+            Instruction::OrReg(vx, vy) => 0x8001 | x(vx)? | y(vy)?,
+            Instruction::AndReg(vx, vy) => 0x8002 | x(vx)? | y(vy)?,
+            Instruction::XorReg(vx, vy) => 0x8003 | x(vx)? | y(vy)?,
+            Instruction::AddReg(vx, vy) => 0x8004 | x(vx)? | y(vy)?,
+            Instruction::SubReg(vx, vy) => 0x8005 | x(vx)? | y(vy)?,
+            Instruction::ShrReg(vx, vy) => 0x8006 | x(vx)? | y(vy)?,
+            Instruction::SubnReg(vx, vy) => 0x8007 | x(vx)? | y(vy)?,
+            Instruction::ShlReg(vx, vy) => 0x800E | x(vx)? | y(vy)?,
+            Instruction::SkipNeReg(vx, vy) => 0x9000 | x(vx)? | y(vy)?,
+            Instruction::LoadI(addr) => 0xA000 | nnn(addr)?,
+            Instruction::JumpV0(addr) => 0xB000 | nnn(addr)?,
+            Instruction::Rand(vx, byte) => 0xC000 | x(vx)? | u16::from(byte),
+            Instruction::Draw(vx, vy, rows) => 0xD000 | x(vx)? | y(vy)? | n(rows)?,
+            Instruction::SkipIfKey(vx) => 0xE09E | x(vx)?,
+            Instruction::SkipIfNotKey(vx) => 0xE0A1 | x(vx)?,
+            Instruction::LoadILong => 0xF000,
+            Instruction::Plane(planes) => 0xF001 | x(planes)?,
+            Instruction::AudioPattern => 0xF002,
+            Instruction::LoadDT(vx) => 0xF007 | x(vx)?,
+            Instruction::WaitKey(vx) => 0xF00A | x(vx)?,
+            Instruction::SetDT(vx) => 0xF015 | x(vx)?,
+            Instruction::SetST(vx) => 0xF018 | x(vx)?,
+            Instruction::AddI(vx) => 0xF01E | x(vx)?,
+            Instruction::LoadSprite(vx) => 0xF029 | x(vx)?,
+            Instruction::LoadBigSprite(vx) => 0xF030 | x(vx)?,
+            Instruction::Bcd(vx) => 0xF033 | x(vx)?,
+            Instruction::Pitch(vx) => 0xF03A | x(vx)?,
+            Instruction::DumpRegs(vx) => 0xF055 | x(vx)?,
+            Instruction::LoadRegs(vx) => 0xF065 | x(vx)?,
+            Instruction::StoreFlags(vx) => 0xF075 | x(vx)?,
+            Instruction::LoadFlags(vx) => 0xF085 | x(vx)?,
+            Instruction::NoOp => return Err(EncodeError::NoOp),
+        };
+        Ok(opcode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_all_opcodes() {
+        for opcode in 0..=u16::MAX {
+            let ins = Instruction::from(opcode);
+            if ins == Instruction::NoOp {
+                assert_eq!(u16::try_from(ins), Err(EncodeError::NoOp));
+            } else {
+                assert_eq!(u16::try_from(ins), Ok(opcode), "{:?}", ins);
+            }
+        }
+    }
+
+    #[test]
+    fn test_operand_out_of_range() {
+        for ins in [
+            Instruction::Jump(0x1000),
+            Instruction::LoadByte(0x10, 0x00),
+            Instruction::LoadReg(0x1, 0x10),
+            Instruction::Draw(0x1, 0x2, 0x10),
+            Instruction::Plane(0x10),
+        ] {
+            assert_eq!(u16::try_from(ins), Err(EncodeError::OperandOutOfRange(ins)));
+        }
+    }
+}
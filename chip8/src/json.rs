@@ -0,0 +1,269 @@
+//! A minimal JSON reader for the ROM database
+
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(BTreeMap<String, Json>),
+}
+
+impl Json {
+    /// Parses a JSON document, returning the byte offset of the first error
+    pub(crate) fn parse(text: &str) -> Result<Json, usize> {
+        let mut parser = Parser {
+            bytes: text.as_bytes(),
+            pos: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos != parser.bytes.len() {
+            return Err(parser.pos);
+        }
+        Ok(value)
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(object) => object.get(key),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(array) => Some(array),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_object(&self) -> Option<&BTreeMap<String, Json>> {
+        match self {
+            Json::Object(object) => Some(object),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|byte| byte.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, literal: &[u8]) -> Result<(), usize> {
+        if self.bytes[self.pos..].starts_with(literal) {
+            self.pos += literal.len();
+            Ok(())
+        } else {
+            Err(self.pos)
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, usize> {
+        self.skip_whitespace();
+        match self.bytes.get(self.pos) {
+            Some(b'n') => self.expect(b"null").map(|_| Json::Null),
+            Some(b't') => self.expect(b"true").map(|_| Json::Bool(true)),
+            Some(b'f') => self.expect(b"false").map(|_| Json::Bool(false)),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'[') => self.array(),
+            Some(b'{') => self.object(),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => Err(self.pos),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, usize> {
+        let start = self.pos;
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|byte| matches!(byte, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9'))
+        {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.bytes[start..self.pos])
+            .ok()
+            .and_then(|number| number.parse().ok())
+            .map(Json::Number)
+            .ok_or(start)
+    }
+
+    fn string(&mut self) -> Result<String, usize> {
+        self.expect(b"\"")?;
+        let mut string = Vec::new();
+        loop {
+            let Some(&byte) = self.bytes.get(self.pos) else {
+                return Err(self.pos);
+            };
+            self.pos += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escaped = match self.bytes.get(self.pos) {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{C}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            let c = self.unicode_escape()?;
+                            let mut utf8 = [0; 4];
+                            string.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+                            continue;
+                        }
+                        _ => return Err(self.pos),
+                    };
+                    self.pos += 1;
+                    string.push(escaped as u8);
+                }
+                byte if byte < 0x20 => return Err(self.pos - 1),
+                byte => string.push(byte),
+            }
+        }
+        // The input is a str, and escapes are pushed as whole characters
+        Ok(String::from_utf8(string).expect("strings are valid UTF-8"))
+    }
+
+    /// Reads `uXXXX` after a backslash, and the low half of a surrogate pair
+    fn unicode_escape(&mut self) -> Result<char, usize> {
+        let start = self.pos - 1;
+        let high = self.hex4()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            self.expect(b"\\")?;
+            let low = self.hex4()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return Err(start);
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or(start)
+    }
+
+    fn hex4(&mut self) -> Result<u32, usize> {
+        self.expect(b"u")?;
+        let digits = self.bytes.get(self.pos..self.pos + 4).ok_or(self.pos)?;
+        let code = std::str::from_utf8(digits)
+            .ok()
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or(self.pos)?;
+        self.pos += 4;
+        Ok(code)
+    }
+
+    fn array(&mut self) -> Result<Json, usize> {
+        self.expect(b"[")?;
+        let mut array = Vec::new();
+        self.skip_whitespace();
+        if self.expect(b"]").is_ok() {
+            return Ok(Json::Array(array));
+        }
+        loop {
+            array.push(self.value()?);
+            self.skip_whitespace();
+            if self.expect(b"]").is_ok() {
+                return Ok(Json::Array(array));
+            }
+            self.expect(b",")?;
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, usize> {
+        self.expect(b"{")?;
+        let mut object = BTreeMap::new();
+        self.skip_whitespace();
+        if self.expect(b"}").is_ok() {
+            return Ok(Json::Object(object));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(b":")?;
+            object.insert(key, self.value()?);
+            self.skip_whitespace();
+            if self.expect(b"}").is_ok() {
+                return Ok(Json::Object(object));
+            }
+            self.expect(b",")?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let json = Json::parse(
+            r#" {"title": "Pong \"2\"", "tickrate": 15, "keys": {"up": 1},
+                 "platforms": ["originalChip8", "xochip"], "ok": true,
+                 "none": null, "e": "\u00e9\ud83d\ude00", "x": -1.5e2} "#,
+        )
+        .unwrap();
+        assert_eq!(json.get("title").unwrap().as_str(), Some("Pong \"2\""));
+        assert_eq!(json.get("tickrate").unwrap().as_f64(), Some(15.0));
+        assert_eq!(
+            json.get("keys").unwrap().get("up").unwrap().as_f64(),
+            Some(1.0)
+        );
+        assert_eq!(json.get("platforms").unwrap().as_array().unwrap().len(), 2);
+        assert_eq!(json.get("ok").unwrap().as_bool(), Some(true));
+        assert_eq!(json.get("none"), Some(&Json::Null));
+        assert_eq!(json.get("e").unwrap().as_str(), Some("é😀"));
+        assert_eq!(json.get("x").unwrap().as_f64(), Some(-150.0));
+        assert_eq!(Json::parse("[]"), Ok(Json::Array(Vec::new())));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(Json::parse(""), Err(0));
+        assert_eq!(Json::parse("[1, 2"), Err(5));
+        assert_eq!(Json::parse("{\"a\" 1}"), Err(5));
+        assert_eq!(Json::parse("[1] x"), Err(4));
+        assert_eq!(Json::parse("\"\\q\""), Err(2));
+        assert_eq!(Json::parse("tru"), Err(0));
+    }
+}
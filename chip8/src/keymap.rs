@@ -0,0 +1,356 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// CHIP-8 keys in the order of the hexadecimal keypad, row by row
+const KEYPAD_ORDER: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+];
+
+/// Names accepted by [`Keymap::preset`]
+pub const KEYMAP_PRESETS: &[&str] = &["qwerty", "azerty", "dvorak", "colemak", "octo"];
+
+/// Maps host keys to CHIP-8 keys. Several host keys can map to the same
+/// CHIP-8 key.
+///
+/// Host keys are named by the character they type, or by one of `Up`,
+/// `Down`, `Left`, `Right`, `Space`, `Enter`, `Tab` and `Backspace`.
+/// Single characters match regardless of case.
+///
+/// The text format is a subset of TOML, with one line per CHIP-8 key:
+///
+/// ```toml
+/// # CHIP-8 key = host keys
+/// 5 = ["w", "Up"]
+/// 6 = "e"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Keymap {
+    keys: BTreeMap<String, u8>,
+}
+
+impl Keymap {
+    /// A keymap without any bindings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 1234/QWER/ASDF/ZXCV, laid out like the hexadecimal keypad
+    pub fn qwerty() -> Self {
+        Self::from_rows(["1234", "qwer", "asdf", "zxcv"])
+    }
+
+    /// The [`Keymap::qwerty`] positions on an AZERTY keyboard
+    pub fn azerty() -> Self {
+        Self::from_rows(["&é\"'", "azer", "qsdf", "wxcv"])
+    }
+
+    /// The [`Keymap::qwerty`] positions on a Dvorak keyboard
+    pub fn dvorak() -> Self {
+        Self::from_rows(["1234", "',.p", "aoeu", ";qjk"])
+    }
+
+    /// The [`Keymap::qwerty`] positions on a Colemak keyboard
+    pub fn colemak() -> Self {
+        Self::from_rows(["1234", "qwfp", "arst", "zxcv"])
+    }
+
+    /// [`Keymap::qwerty`] plus the arrow keys on 5/7/8/9 and space on 6, as
+    /// in Octo
+    pub fn octo() -> Self {
+        let mut keymap = Self::qwerty();
+        for (host, key) in [
+            ("Up", 0x5),
+            ("Left", 0x7),
+            ("Down", 0x8),
+            ("Right", 0x9),
+            ("Space", 0x6),
+        ] {
+            keymap.bind(host, key);
+        }
+        keymap
+    }
+
+    /// The preset called `name`, one of [`KEYMAP_PRESETS`]
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "qwerty" => Some(Self::qwerty()),
+            "azerty" => Some(Self::azerty()),
+            "dvorak" => Some(Self::dvorak()),
+            "colemak" => Some(Self::colemak()),
+            "octo" => Some(Self::octo()),
+            _ => None,
+        }
+    }
+
+    /// Binds the characters of each row to a row of the keypad
+    fn from_rows(rows: [&str; 4]) -> Self {
+        let mut keymap = Self::new();
+        let hosts = rows.iter().flat_map(|row| row.chars());
+        for (host, &key) in hosts.zip(KEYPAD_ORDER.iter()) {
+            keymap.bind(&host.to_string(), key);
+        }
+        keymap
+    }
+
+    /// Maps `host` to `key`, replacing any previous binding of `host`. Keys
+    /// above 0xF are ignored.
+    pub fn bind(&mut self, host: &str, key: u8) {
+        if key < 16 {
+            self.keys.insert(normalize(host), key);
+        }
+    }
+
+    /// Removes the binding of `host`, returning the key it was mapped to
+    pub fn unbind(&mut self, host: &str) -> Option<u8> {
+        self.keys.remove(&normalize(host))
+    }
+
+    /// The CHIP-8 key `host` is mapped to
+    pub fn get(&self, host: &str) -> Option<u8> {
+        self.keys.get(&normalize(host)).copied()
+    }
+
+    /// Host keys mapped to the CHIP-8 `key`
+    pub fn host_keys(&self, key: u8) -> impl Iterator<Item = &str> {
+        self.keys
+            .iter()
+            .filter(move |&(_, &bound)| bound == key)
+            .map(|(host, _)| host.as_str())
+    }
+
+    /// Reads the text format, see [`Keymap`]
+    pub fn parse(text: &str) -> Result<Self, KeymapError> {
+        let mut keymap = Self::new();
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let error = |kind| KeymapError {
+                line: line_number,
+                kind,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, value) = line.split_once('=').ok_or(error(KeymapErrorKind::Syntax))?;
+            let name = name.trim();
+            let key = match u8::from_str_radix(name, 16) {
+                Ok(key) if key < 16 && name.len() == 1 => key,
+                _ => return Err(error(KeymapErrorKind::InvalidKey(name.to_string()))),
+            };
+            let hosts = parse_value(value).ok_or(error(KeymapErrorKind::Syntax))?;
+            for host in hosts {
+                if keymap.get(&host).is_some() {
+                    return Err(error(KeymapErrorKind::DuplicateHostKey(host)));
+                }
+                keymap.bind(&host, key);
+            }
+        }
+        Ok(keymap)
+    }
+}
+
+impl FromStr for Keymap {
+    type Err = KeymapError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Self::parse(text)
+    }
+}
+
+/// Writes the text format read by [`Keymap::parse`]
+impl fmt::Display for Keymap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# CHIP-8 key = host keys")?;
+        for key in KEYPAD_ORDER {
+            let hosts: Vec<String> = self.host_keys(key).map(quote).collect();
+            if !hosts.is_empty() {
+                writeln!(f, "{:X} = [{}]", key, hosts.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeymapError {
+    pub line: usize,
+    pub kind: KeymapErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeymapErrorKind {
+    /// The line is not `KEY = "host"` or `KEY = ["host", ...]`
+    Syntax,
+    /// The CHIP-8 key is not a hexadecimal digit
+    InvalidKey(String),
+    /// The host key is already mapped
+    DuplicateHostKey(String),
+}
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            KeymapErrorKind::Syntax => write!(f, "expected KEY = [\"host key\", ...]"),
+            KeymapErrorKind::InvalidKey(key) => write!(f, "invalid CHIP-8 key '{}'", key),
+            KeymapErrorKind::DuplicateHostKey(host) => {
+                write!(f, "host key '{}' is mapped more than once", host)
+            }
+        }
+    }
+}
+
+impl std::error::Error for KeymapError {}
+
+/// Single characters are matched regardless of case
+fn normalize(host: &str) -> String {
+    let mut chars = host.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => c.to_lowercase().collect(),
+        _ => host.to_string(),
+    }
+}
+
+fn quote(host: &str) -> String {
+    format!("\"{}\"", host.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Parses a string or an array of strings, optionally followed by a comment
+fn parse_value(value: &str) -> Option<Vec<String>> {
+    let mut chars = value.trim_start().chars().peekable();
+    let mut hosts = Vec::new();
+    if chars.peek() == Some(&'[') {
+        chars.next();
+        loop {
+            skip_spaces(&mut chars);
+            match chars.peek() {
+                Some(']') => {
+                    chars.next();
+                    break;
+                }
+                Some('"') => hosts.push(parse_string(&mut chars)?),
+                _ => return None,
+            }
+            skip_spaces(&mut chars);
+            match chars.next() {
+                Some(',') => {}
+                Some(']') => break,
+                _ => return None,
+            }
+        }
+    } else {
+        hosts.push(parse_string(&mut chars)?);
+    }
+    skip_spaces(&mut chars);
+    match chars.next() {
+        None | Some('#') => Some(hosts),
+        _ => None,
+    }
+}
+
+fn skip_spaces(chars: &mut std::iter::Peekable<std::str::Chars>) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+fn parse_string(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<String> {
+    if chars.next() != Some('"') {
+        return None;
+    }
+    let mut string = String::new();
+    loop {
+        match chars.next()? {
+            '"' => break,
+            '\\' => string.push(chars.next()?),
+            c => string.push(c),
+        }
+    }
+    (!string.is_empty()).then_some(string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets() {
+        let qwerty = Keymap::qwerty();
+        assert_eq!(qwerty.get("1"), Some(0x1));
+        assert_eq!(qwerty.get("4"), Some(0xC));
+        assert_eq!(qwerty.get("x"), Some(0x0));
+        assert_eq!(qwerty.get("V"), Some(0xF));
+        assert_eq!(qwerty.get("Up"), None);
+
+        let azerty = Keymap::azerty();
+        assert_eq!(azerty.get("a"), Some(0x4));
+        assert_eq!(azerty.get("é"), Some(0x2));
+        assert_eq!(azerty.get("w"), Some(0xA));
+
+        let octo = Keymap::octo();
+        assert_eq!(octo.get("w"), Some(0x5));
+        assert_eq!(octo.get("Up"), Some(0x5));
+        assert_eq!(octo.host_keys(0x5).collect::<Vec<_>>(), ["Up", "w"]);
+
+        for name in KEYMAP_PRESETS {
+            let keymap = Keymap::preset(name).unwrap();
+            for key in 0..16 {
+                assert!(keymap.host_keys(key).next().is_some(), "{} {:X}", name, key);
+            }
+        }
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        for name in KEYMAP_PRESETS {
+            let keymap = Keymap::preset(name).unwrap();
+            assert_eq!(keymap.to_string().parse(), Ok(keymap));
+        }
+
+        let mut keymap = Keymap::new();
+        keymap.bind("\"", 0x1);
+        keymap.bind("\\", 0x1);
+        assert_eq!(
+            keymap.to_string(),
+            "# CHIP-8 key = host keys\n1 = [\"\\\"\", \"\\\\\"]\n"
+        );
+        assert_eq!(Keymap::parse(&keymap.to_string()), Ok(keymap));
+    }
+
+    #[test]
+    fn test_parse() {
+        let keymap = Keymap::parse(
+            "# arrows\n\
+             5 = [\"Up\", \"k\"]  # vi\n\
+             \n\
+             a = \"Space\"\n\
+             7 = []\n",
+        )
+        .unwrap();
+        assert_eq!(keymap.get("Up"), Some(0x5));
+        assert_eq!(keymap.get("K"), Some(0x5));
+        assert_eq!(keymap.get("Space"), Some(0xA));
+        assert_eq!(keymap.host_keys(0x7).count(), 0);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |line, kind| Err(KeymapError { line, kind });
+        assert_eq!(
+            Keymap::parse("\n5 [\"w\"]"),
+            error(2, KeymapErrorKind::Syntax)
+        );
+        assert_eq!(
+            Keymap::parse("5 = [\"w\""),
+            error(1, KeymapErrorKind::Syntax)
+        );
+        assert_eq!(Keymap::parse("5 = w"), error(1, KeymapErrorKind::Syntax));
+        assert_eq!(
+            Keymap::parse("10 = \"w\""),
+            error(1, KeymapErrorKind::InvalidKey("10".to_string()))
+        );
+        assert_eq!(
+            Keymap::parse("5 = \"w\"\n6 = \"W\""),
+            error(2, KeymapErrorKind::DuplicateHostKey("W".to_string()))
+        );
+    }
+}
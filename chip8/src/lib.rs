@@ -1,12 +1,58 @@
+mod assembler;
+mod audio;
+mod debugger;
+mod disassembler;
+mod error;
+mod headless;
+mod inspect;
 mod instruction;
+mod json;
+mod keymap;
+mod movie;
+mod observer;
+mod octo;
+mod quirks;
+mod recording;
+mod rewind;
+mod rng;
+mod romdb;
+mod screenshot;
+mod sha1;
+mod state;
 
-use crate::instruction::Instruction;
+use std::ops::Range;
+
+pub use crate::assembler::{AssembleError, AssembleErrorKind, assemble, assemble_file};
+pub use crate::audio::{Buzzer, DEFAULT_FREQUENCY, Waveform, write_wav};
+pub use crate::debugger::{Debugger, Register, StopReason, Watch};
+pub use crate::disassembler::{Line, LineKind, Listing, Syntax, disassemble};
+pub use crate::error::Chip8Error;
+pub use crate::headless::{GoldenError, Headless};
+pub use crate::inspect::Chip8State;
+pub use crate::instruction::{EncodeError, Instruction};
+pub use crate::keymap::{KEYMAP_PRESETS, Keymap, KeymapError, KeymapErrorKind};
+pub use crate::movie::{MOVIE_VERSION, Movie, MovieError, MoviePlayer, MovieRecorder};
+pub use crate::observer::{Observer, Timer};
+pub use crate::octo::{OctoProgram, compile_octo};
+pub use crate::quirks::{LoadStoreIncrement, Quirks};
+pub use crate::recording::{GifRecorder, PngSequenceRecorder};
+pub use crate::rewind::Rewind;
+pub use crate::rng::{RandomSource, Xorshift64};
+pub use crate::romdb::{RomDatabase, RomDatabaseError, RomInfo};
+pub use crate::screenshot::{Palette, Screenshot};
+pub use crate::state::{STATE_VERSION, StateError};
 
 const OPS_START_ADDRESS: u16 = 0x200;
+const MEMORY_SIZE: usize = 0x1000;
+const XO_MEMORY_SIZE: usize = 0x10000;
 const FONTSET_START_ADDRESS: u16 = 0x50;
 const FONTSET_SIZE: usize = 80;
-const VIDEO_WIDTH: u16 = 64;
-const VIDEO_HEIGHT: u16 = 32;
+const BIG_FONTSET_START_ADDRESS: u16 = 0xA0;
+const BIG_FONTSET_SIZE: usize = 160;
+const LORES_WIDTH: u16 = 64;
+const LORES_HEIGHT: u16 = 32;
+const HIRES_WIDTH: u16 = 128;
+const HIRES_HEIGHT: u16 = 64;
 
 const FONTSET: [u8; FONTSET_SIZE] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -27,82 +73,366 @@ const FONTSET: [u8; FONTSET_SIZE] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+const BIG_FONTSET: [u8; BIG_FONTSET_SIZE] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+/// Instruction set understood by the interpreter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Mode {
+    /// The original CHIP-8 instruction set
+    #[default]
+    Chip8,
+    /// SUPER-CHIP 1.1: 128x64 high resolution mode, scrolling, 16x16 sprites,
+    /// a large font and RPL user flags
+    SuperChip,
+    /// XO-CHIP: SUPER-CHIP plus 64 KiB of memory, two bitplanes and audio patterns
+    XoChip,
+}
+
 #[derive(Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(remote = "Self")
+)]
 pub struct Chip8 {
-    memory: [u8; 4096],
+    memory: Vec<u8>,
     v: [u8; 16],
     i: u16,
     pc: u16,
-    gfx: [u8; 64 * 32],
+    gfx: Vec<u8>,
+    /// Rows changed since the display was last checked, one bit per row
+    #[cfg_attr(feature = "serde", serde(skip, default = "all_rows_dirty"))]
+    dirty_rows: u64,
+    hires: bool,
+    planes: u8,
+    audio_pattern: [u8; 16],
+    pitch: u8,
     delay_timer: u8,
     sound_timer: u8,
     stack: [u16; 16],
     sp: u8,
     keypad: [u8; 16],
-    debug: bool,
+    /// Keys pressed since 0xFX0A started waiting, one bit per key
+    key_presses: u16,
+    /// Keys released since 0xFX0A started waiting, one bit per key
+    key_releases: u16,
+    rpl_flags: [u8; 16],
+    mode: Mode,
+    quirks: Quirks,
+    waiting_vblank: bool,
+    /// Whether 0xFX0A is waiting for a key
+    waiting_key: bool,
+    halted: bool,
+    #[cfg_attr(feature = "serde", serde(with = "rng::serde_state"))]
+    rng: Box<dyn RandomSource>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    observer: Option<Box<dyn Observer>>,
+    /// Decoded instructions by address, see [`Chip8::set_decode_cache`]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    decode_cache: Option<Vec<Option<(u16, Instruction)>>>,
+}
+
+impl Default for Chip8 {
+    fn default() -> Self {
+        Self::new(Quirks::default())
+    }
 }
 
 impl Chip8 {
-    pub fn new() -> Self {
-        let mut memory = [0; 4096];
+    pub fn new(quirks: Quirks) -> Self {
+        Self::with_mode(Mode::Chip8, quirks)
+    }
+
+    pub fn with_mode(mode: Mode, quirks: Quirks) -> Self {
+        Self::with_rng(mode, quirks, Box::new(Xorshift64::default()))
+    }
+
+    /// Creates an interpreter drawing the random bytes for 0xCXNN from `rng`.
+    /// Two interpreters with identically seeded sources and the same inputs
+    /// run identically.
+    pub fn with_rng(mode: Mode, quirks: Quirks, rng: Box<dyn RandomSource>) -> Self {
+        let memory_size = match mode {
+            Mode::Chip8 | Mode::SuperChip => MEMORY_SIZE,
+            Mode::XoChip => XO_MEMORY_SIZE,
+        };
+        let mut memory = vec![0; memory_size];
         memory[FONTSET_START_ADDRESS as usize..(FONTSET_START_ADDRESS as usize + FONTSET_SIZE)]
             .copy_from_slice(&FONTSET);
+        memory[BIG_FONTSET_START_ADDRESS as usize
+            ..(BIG_FONTSET_START_ADDRESS as usize + BIG_FONTSET_SIZE)]
+            .copy_from_slice(&BIG_FONTSET);
 
         Chip8 {
-            memory: memory,
+            memory,
             v: [0; 16],
             i: 0,
             pc: OPS_START_ADDRESS,
-            gfx: [0; 64 * 32],
+            gfx: vec![0; HIRES_WIDTH as usize * HIRES_HEIGHT as usize],
+            dirty_rows: all_rows_dirty(),
+            hires: false,
+            planes: 1,
+            audio_pattern: [0; 16],
+            pitch: 64,
             delay_timer: 0,
             sound_timer: 0,
             stack: [0; 16],
             sp: 0,
             keypad: [0; 16],
-            debug: false,
+            key_presses: 0,
+            key_releases: 0,
+            rpl_flags: [0; 16],
+            mode,
+            quirks,
+            waiting_vblank: false,
+            waiting_key: false,
+            halted: false,
+            rng,
+            observer: None,
+            decode_cache: None,
         }
     }
 
-    pub fn enable_debug(&mut self) {
-        self.debug = true;
+    /// Installs `observer` to be called back as the program runs, replacing
+    /// any previous one. Without an observer no events are produced.
+    pub fn set_observer(&mut self, observer: Box<dyn Observer>) {
+        self.observer = Some(observer);
+    }
+
+    pub fn remove_observer(&mut self) -> Option<Box<dyn Observer>> {
+        self.observer.take()
+    }
+
+    /// Enables or disables caching decoded instructions by address, which
+    /// speeds up long runs. Entries are dropped whenever the bytes they were
+    /// decoded from are written, so self-modifying programs stay correct.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache = enabled.then(|| vec![None; self.memory.len()]);
     }
 
-    pub fn load_rom(&mut self, bytes: &Vec<u8>) {
+    pub fn decode_cache_enabled(&self) -> bool {
+        self.decode_cache.is_some()
+    }
+
+    /// Drops cached instructions overlapping the bytes in `range`
+    fn invalidate_decoded(&mut self, range: Range<usize>) {
+        if let Some(cache) = &mut self.decode_cache {
+            let start = range.start.saturating_sub(1);
+            let end = range.end.min(cache.len());
+            cache[start..end].fill(None);
+        }
+    }
+
+    pub fn load_rom(&mut self, bytes: &[u8]) -> Result<(), Chip8Error> {
         let start = OPS_START_ADDRESS as usize;
+        let max = self.memory.len() - start;
+        if bytes.len() > max {
+            return Err(Chip8Error::RomTooLarge {
+                size: bytes.len(),
+                max,
+            });
+        }
         let end = start + bytes.len();
-        self.memory[start..end].copy_from_slice(&bytes);
+        self.memory[start..end].copy_from_slice(bytes);
+        self.invalidate_decoded(start..end);
+        Ok(())
     }
 
+    /// Sets the state of `key`. Changes are also recorded as press and
+    /// release edges, which 0xFX0A waits for.
     pub fn keypress(&mut self, key: usize, pressed: bool) {
         if key < 16 {
+            let was_pressed = self.keypad[key] != 0;
+            if pressed && !was_pressed {
+                self.key_presses |= 1 << key;
+            } else if !pressed && was_pressed {
+                self.key_releases |= 1 << key;
+            }
             self.keypad[key] = u8::from(pressed);
         }
     }
 
+    /// Width in pixels of the current display mode
+    pub fn width(&self) -> u16 {
+        if self.hires { HIRES_WIDTH } else { LORES_WIDTH }
+    }
+
+    /// Height in pixels of the current display mode
+    pub fn height(&self) -> u16 {
+        if self.hires {
+            HIRES_HEIGHT
+        } else {
+            LORES_HEIGHT
+        }
+    }
+
+    /// Colour index of the pixel at (x, y): bit 0 is set when the pixel is lit
+    /// on the first bitplane and bit 1 when it is lit on the second (XO-CHIP)
     pub fn pixel_at(&self, x: u16, y: u16) -> u8 {
-        let index = (x + y * VIDEO_WIDTH) as usize;
+        let index = (x + y * self.width()) as usize;
         self.gfx[index]
     }
 
-    fn pop_opcode(&mut self) -> Instruction {
-        let high_byte = self.memory[self.pc as usize];
-        let low_byte = self.memory[(self.pc + 1) as usize];
-        self.pc += 2;
-        let val = (u16::from(high_byte) << 8) | u16::from(low_byte);
-        Instruction::from(val)
+    /// Colour indices of the current display mode, row by row, as returned by
+    /// [`Chip8::pixel_at`]
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.gfx[..usize::from(self.width()) * usize::from(self.height())]
+    }
+
+    /// The display packed 8 pixels to a byte, leftmost pixel in the most
+    /// significant bit. A pixel is set when it is lit on any bitplane.
+    pub fn framebuffer_1bpp(&self) -> Vec<u8> {
+        self.framebuffer()
+            .chunks(8)
+            .map(|pixels| {
+                pixels
+                    .iter()
+                    .fold(0, |byte, pixel| (byte << 1) | u8::from(*pixel != 0))
+            })
+            .collect()
+    }
+
+    /// Whether the display changed since the last call, or since the dirty
+    /// rows were last taken. Clears the dirty rows.
+    pub fn frame_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty_rows) != 0
+    }
+
+    /// Rows of the current display mode that changed since the last call, or
+    /// since [`Chip8::frame_dirty`] was last called, and clears them
+    pub fn take_dirty_rows(&mut self) -> impl Iterator<Item = u16> + use<> {
+        let rows = std::mem::take(&mut self.dirty_rows);
+        (0..self.height()).filter(move |row| rows & (1 << row) != 0)
+    }
+
+    /// The 16 byte (128 sample) 1-bit audio pattern loaded by 0xF002
+    pub fn audio_pattern(&self) -> &[u8; 16] {
+        &self.audio_pattern
+    }
+
+    /// Playback rate of the audio pattern in Hz, as set by 0xFX3A
+    pub fn pattern_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((f32::from(self.pitch) - 64.0) / 48.0)
+    }
+
+    /// Whether the buzzer sounds, which it does while the sound timer is
+    /// non-zero
+    pub fn is_beeping(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    /// Whether the program has exited through 0x00FD
+    pub fn is_halted(&self) -> bool {
+        self.halted
     }
 
-    pub fn tick(&mut self) {
-        if self.debug {
-            println!("[INFO] PC: {:#06x} - {}", self.pc, self.pc);
-            println!("[INFO] I: {:#06x} - {}", self.i, self.i);
-            println!("[INFO] SP: {:#06x} - {}", self.sp, self.sp);
-            println!("[INFO] V: {:?}", self.v);
-            println!("[INFO] Stack: {:?}", &self.stack[..self.sp as usize]);
+    /// Checks that `len` bytes starting at `addr` are within memory. Errors
+    /// blame the instruction just before PC, so PC must already be advanced.
+    fn mem_range(&self, addr: usize, len: usize) -> Result<Range<usize>, Chip8Error> {
+        if addr + len > self.memory.len() {
+            return Err(Chip8Error::MemoryOutOfBounds {
+                addr: addr.max(self.memory.len()),
+                pc: self.pc.wrapping_sub(2),
+            });
         }
-        let opcode = self.pop_opcode();
-        self.execute(opcode);
+        Ok(addr..addr + len)
+    }
+
+    /// Moves I on after 0xFX55/0xFX65 stored or loaded V0 through VX
+    fn increment_i_after_load_store(&mut self, x: u8) {
+        let increment = match self.quirks.load_store_increment {
+            LoadStoreIncrement::Unchanged => return,
+            LoadStoreIncrement::ByX => u16::from(x),
+            LoadStoreIncrement::ByXPlusOne => u16::from(x) + 1,
+        };
+        self.i = self.i.wrapping_add(increment);
+    }
+
+    fn read_word(&self, addr: u16) -> Result<u16, Chip8Error> {
+        let range = self.mem_range(addr as usize, 2)?;
+        let high_byte = self.memory[range.start];
+        let low_byte = self.memory[range.start + 1];
+        Ok((u16::from(high_byte) << 8) | u16::from(low_byte))
+    }
 
+    /// The opcode at `addr` and its instruction, from the decode cache when
+    /// enabled
+    fn decode_at(&mut self, addr: u16) -> Result<(u16, Instruction), Chip8Error> {
+        let cached = self
+            .decode_cache
+            .as_ref()
+            .and_then(|cache| cache.get(usize::from(addr)).copied().flatten());
+        if let Some(decoded) = cached {
+            return Ok(decoded);
+        }
+        let val = self.read_word(addr)?;
+        let decoded = (val, Instruction::from(val));
+        if let Some(cache) = &mut self.decode_cache {
+            cache[usize::from(addr)] = Some(decoded);
+        }
+        Ok(decoded)
+    }
+
+    fn pop_opcode(&mut self) -> Result<(u16, Instruction), Chip8Error> {
+        let pc = self.pc;
+        self.pc = pc.wrapping_add(2);
+        self.decode_at(pc)
+    }
+
+    /// Skips the next instruction, stepping over both words of 0xF000 NNNN on XO-CHIP
+    fn skip_next(&mut self) -> Result<(), Chip8Error> {
+        let size = match self.mode {
+            Mode::XoChip => self.decode_at(self.pc)?.1.size(),
+            _ => 2,
+        };
+        self.pc = self.pc.wrapping_add(size);
+        Ok(())
+    }
+
+    /// Executes a single instruction. Timers are left untouched, see
+    /// [`Chip8::tick_timers`] and [`Chip8::run_frame`].
+    pub fn tick(&mut self) -> Result<(), Chip8Error> {
+        if self.waiting_vblank || self.halted {
+            return Ok(());
+        }
+        let pc = self.pc;
+        let (opcode, ins) = self.pop_opcode()?;
+        if ins == Instruction::NoOp {
+            if let Some(observer) = &mut self.observer {
+                observer.on_unknown_opcode(pc, opcode);
+            }
+            return Err(Chip8Error::InvalidOpcode { opcode, pc });
+        }
+        if let Some(observer) = &mut self.observer {
+            observer.on_instruction(pc, &ins);
+        }
+        self.execute(ins)
+    }
+
+    /// Steps the delay and sound timers once. Should be called at 60 Hz,
+    /// independently of how many instructions are executed.
+    pub fn tick_timers(&mut self) {
+        self.waiting_vblank = false;
+        if self.waiting_key && self.quirks.key_wait_release {
+            return;
+        }
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
@@ -111,38 +441,113 @@ impl Chip8 {
         }
     }
 
-    fn execute(&mut self, ins: Instruction) {
-        if self.debug {
-            println!("[INFO] Executing: {:?}", ins);
+    /// Runs one 60 Hz frame: executes `instructions_per_frame` instructions
+    /// and then steps the timers once.
+    pub fn run_frame(&mut self, instructions_per_frame: u32) -> Result<(), Chip8Error> {
+        for _ in 0..instructions_per_frame {
+            if self.waiting_vblank {
+                break;
+            }
+            self.tick()?;
+        }
+        self.tick_timers();
+        Ok(())
+    }
+
+    /// Writes a byte on behalf of an instruction, reporting it to the observer
+    fn write_memory(&mut self, addr: usize, value: u8) {
+        let old = self.memory[addr];
+        self.memory[addr] = value;
+        self.invalidate_decoded(addr..addr + 1);
+        if let Some(observer) = &mut self.observer {
+            observer.on_memory_write(addr as u16, old, value);
         }
+    }
+
+    fn execute(&mut self, ins: Instruction) -> Result<(), Chip8Error> {
         match ins {
+            Instruction::ScrollDown(_)
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::Exit
+            | Instruction::Lores
+            | Instruction::Hires
+            | Instruction::LoadBigSprite(_)
+            | Instruction::StoreFlags(_)
+            | Instruction::LoadFlags(_)
+                if self.mode == Mode::Chip8 => {}
+            Instruction::SaveRange(..)
+            | Instruction::LoadRange(..)
+            | Instruction::LoadILong
+            | Instruction::Plane(_)
+            | Instruction::AudioPattern
+            | Instruction::Pitch(_)
+                if self.mode != Mode::XoChip => {}
             Instruction::Cls => {
+                let planes = self.planes;
+                self.gfx.iter_mut().for_each(|pixel| *pixel &= !planes);
+                self.dirty_rows = all_rows_dirty();
+            }
+            Instruction::ScrollDown(n) => self.scroll_down(u16::from(n)),
+            Instruction::ScrollRight => self.scroll_right(4),
+            Instruction::ScrollLeft => self.scroll_left(4),
+            Instruction::Exit => self.halted = true,
+            Instruction::Lores => {
+                self.hires = false;
+                self.gfx.fill(0);
+                self.dirty_rows = all_rows_dirty();
+            }
+            Instruction::Hires => {
+                self.hires = true;
                 self.gfx.fill(0);
+                self.dirty_rows = all_rows_dirty();
             }
             Instruction::Ret => {
+                if self.sp == 0 {
+                    return Err(Chip8Error::StackUnderflow {
+                        pc: self.pc.wrapping_sub(2),
+                    });
+                }
                 self.sp -= 1;
                 self.pc = self.stack[(self.sp) as usize];
             }
             Instruction::Sys(_) => {}
             Instruction::Jump(addr) => self.pc = addr,
             Instruction::Call(addr) => {
+                if self.sp as usize == self.stack.len() {
+                    return Err(Chip8Error::StackOverflow {
+                        pc: self.pc.wrapping_sub(2),
+                    });
+                }
                 self.stack[self.sp as usize] = self.pc;
                 self.sp += 1;
                 self.pc = addr;
             }
             Instruction::SkipEqByte(x, byte) => {
                 if self.v[x as usize] == byte {
-                    self.pc += 2;
+                    self.skip_next()?;
                 }
             }
             Instruction::SkipNeByte(x, byte) => {
                 if self.v[x as usize] != byte {
-                    self.pc += 2;
+                    self.skip_next()?;
                 }
             }
             Instruction::SkipEqReg(x, y) => {
                 if self.v[x as usize] == self.v[y as usize] {
-                    self.pc += 2;
+                    self.skip_next()?;
+                }
+            }
+            Instruction::SaveRange(x, y) => {
+                let range = self.mem_range(self.i as usize, x.abs_diff(y) as usize + 1)?;
+                for (addr, reg) in range.zip(register_range(x, y)) {
+                    self.write_memory(addr, self.v[reg]);
+                }
+            }
+            Instruction::LoadRange(x, y) => {
+                let range = self.mem_range(self.i as usize, x.abs_diff(y) as usize + 1)?;
+                for (addr, reg) in range.zip(register_range(x, y)) {
+                    self.v[reg] = self.memory[addr];
                 }
             }
             Instruction::LoadByte(x, byte) => {
@@ -156,12 +561,21 @@ impl Chip8 {
             }
             Instruction::OrReg(x, y) => {
                 self.v[x as usize] |= self.v[y as usize];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
             }
             Instruction::AndReg(x, y) => {
                 self.v[x as usize] &= self.v[y as usize];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
             }
             Instruction::XorReg(x, y) => {
                 self.v[x as usize] ^= self.v[y as usize];
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
             }
             Instruction::AddReg(x, y) => {
                 let sum = u16::from(self.v[x as usize]) + u16::from(self.v[y as usize]);
@@ -176,11 +590,16 @@ impl Chip8 {
                 self.v[x] = x_val.wrapping_sub(y_val);
                 self.v[0xF] = u8::from(x_val >= y_val);
             }
-            Instruction::ShrReg(x, _) => {
+            Instruction::ShrReg(x, y) => {
                 let x = x as usize;
-                let x_val = self.v[x];
-                self.v[x] >>= 1;
-                self.v[0xF] = x_val & 0x01;
+                let src = if self.quirks.shift_uses_vy {
+                    y as usize
+                } else {
+                    x
+                };
+                let src_val = self.v[src];
+                self.v[x] = src_val >> 1;
+                self.v[0xF] = src_val & 0x01;
             }
             Instruction::SubnReg(x, y) => {
                 let x = x as usize;
@@ -190,99 +609,254 @@ impl Chip8 {
                 self.v[x] = y_val.wrapping_sub(x_val);
                 self.v[0xF] = u8::from(y_val >= x_val);
             }
-            Instruction::ShlReg(x, _) => {
+            Instruction::ShlReg(x, y) => {
                 let x = x as usize;
-                let x_val = self.v[x];
-                self.v[x] <<= 1;
-                self.v[0xF] = (x_val & 0x80) >> 7;
+                let src = if self.quirks.shift_uses_vy {
+                    y as usize
+                } else {
+                    x
+                };
+                let src_val = self.v[src];
+                self.v[x] = src_val << 1;
+                self.v[0xF] = (src_val & 0x80) >> 7;
             }
             Instruction::SkipNeReg(x, y) => {
                 if self.v[x as usize] != self.v[y as usize] {
-                    self.pc += 2;
+                    self.skip_next()?;
                 }
             }
             Instruction::LoadI(addr) => {
                 self.i = addr;
             }
             Instruction::JumpV0(addr) => {
-                self.pc = addr + u16::from(self.v[0]);
+                let reg = if self.quirks.jump_uses_vx {
+                    ((addr & 0x0F00) >> 8) as usize
+                } else {
+                    0
+                };
+                self.pc = addr + u16::from(self.v[reg]);
             }
             Instruction::Rand(x, byte) => {
-                let rnd: u8 = rand::random();
+                let rnd = self.rng.next_byte();
                 self.v[x as usize] = rnd & byte;
             }
             Instruction::Draw(x, y, n) => {
-                let vx = self.v[x as usize] as u16;
-                let vy = self.v[y as usize] as u16;
+                let width = self.width();
+                let height = self.height();
+                // The starting position always wraps, only the sprite itself is clipped
+                let vx = self.v[x as usize] as u16 % width;
+                let vy = self.v[y as usize] as u16 % height;
+                let (rows, cols) = if n == 0 && self.mode != Mode::Chip8 {
+                    (16, 16)
+                } else {
+                    (u16::from(n), 8)
+                };
+                let plane_bytes = usize::from(rows * (cols / 8));
+                let sprite = self.mem_range(
+                    self.i as usize,
+                    plane_bytes * self.planes.count_ones() as usize,
+                )?;
                 self.v[0xF] = 0;
-                for byte_index in 0..n {
-                    let sprite_byte = self.memory[(self.i + byte_index as u16) as usize];
-                    for bit_index in 0..8 {
-                        let pixel_value = (sprite_byte >> (7 - bit_index)) & 0x01;
-                        let x_coord = (vx + bit_index) % VIDEO_WIDTH;
-                        let y_coord = (vy + byte_index as u16) % VIDEO_HEIGHT;
-                        let gfx_index = (x_coord + y_coord * VIDEO_WIDTH) as usize;
-                        if pixel_value == 1 {
-                            if self.gfx[gfx_index] == 1 {
-                                self.v[0xF] = 1;
+                // Each selected plane reads its own sprite data, one after the other
+                let mut sprite_addr = sprite.start;
+                for plane in [0x1, 0x2] {
+                    if self.planes & plane == 0 {
+                        continue;
+                    }
+                    for row in 0..rows {
+                        for col in 0..cols {
+                            let sprite_byte =
+                                self.memory[sprite_addr + usize::from(row * (cols / 8) + col / 8)];
+                            let pixel_value = (sprite_byte >> (7 - col % 8)) & 0x01;
+                            let x_coord = vx + col;
+                            let y_coord = vy + row;
+                            if self.quirks.clipping && (x_coord >= width || y_coord >= height) {
+                                continue;
+                            }
+                            let x_coord = x_coord % width;
+                            let y_coord = y_coord % height;
+                            let gfx_index = (x_coord + y_coord * width) as usize;
+                            if pixel_value == 1 {
+                                if self.gfx[gfx_index] & plane != 0 {
+                                    self.v[0xF] = 1;
+                                }
+                                self.gfx[gfx_index] ^= plane;
+                                self.dirty_rows |= 1 << y_coord;
                             }
-                            self.gfx[gfx_index] ^= 1;
                         }
                     }
+                    sprite_addr += plane_bytes;
+                }
+                if let Some(observer) = &mut self.observer {
+                    observer.on_draw(vx, vy, rows, self.v[0xF] == 1);
+                }
+                if self.quirks.display_wait {
+                    self.waiting_vblank = true;
                 }
             }
             Instruction::SkipIfKey(x) => {
-                if self.keypad[self.v[x as usize] as usize] != 0 {
-                    self.pc += 2;
+                if self.keypad[(self.v[x as usize] & 0x0F) as usize] != 0 {
+                    self.skip_next()?;
                 }
             }
             Instruction::SkipIfNotKey(x) => {
-                if self.keypad[self.v[x as usize] as usize] == 0 {
-                    self.pc += 2;
+                if self.keypad[(self.v[x as usize] & 0x0F) as usize] == 0 {
+                    self.skip_next()?;
                 }
             }
+            Instruction::LoadILong => {
+                self.i = self.read_word(self.pc)?;
+                self.pc = self.pc.wrapping_add(2);
+            }
+            Instruction::Plane(n) => {
+                self.planes = n & 0x3;
+            }
+            Instruction::AudioPattern => {
+                let range = self.mem_range(self.i as usize, 16)?;
+                self.audio_pattern.copy_from_slice(&self.memory[range]);
+            }
+            Instruction::Pitch(x) => {
+                self.pitch = self.v[x as usize];
+            }
             Instruction::LoadDT(x) => {
                 self.v[x as usize] = self.delay_timer;
             }
             Instruction::WaitKey(x) => {
-                for (i, &key) in self.keypad.iter().enumerate() {
-                    if key != 0 {
-                        self.v[x as usize] = i as u8;
-                        return;
-                    }
+                // Only keys going down after the wait started count, so a
+                // key held from before does not complete it straight away
+                if !self.waiting_key {
+                    self.waiting_key = true;
+                    self.key_presses = 0;
+                    self.key_releases = 0;
+                }
+                let keys = if self.quirks.key_wait_release {
+                    self.key_presses & self.key_releases
+                } else {
+                    self.key_presses
+                };
+                if keys == 0 {
+                    self.pc = self.pc.wrapping_sub(2);
+                } else {
+                    self.waiting_key = false;
+                    self.v[x as usize] = keys.trailing_zeros() as u8;
                 }
-                self.pc -= 2;
             }
             Instruction::SetDT(x) => {
                 self.delay_timer = self.v[x as usize];
+                if let Some(observer) = &mut self.observer {
+                    observer.on_timer_set(Timer::Delay, self.delay_timer);
+                }
             }
             Instruction::SetST(x) => {
                 self.sound_timer = self.v[x as usize];
+                if let Some(observer) = &mut self.observer {
+                    observer.on_timer_set(Timer::Sound, self.sound_timer);
+                }
             }
             Instruction::AddI(x) => {
-                self.i += u16::from(self.v[x as usize]);
+                self.i = self.i.wrapping_add(u16::from(self.v[x as usize]));
             }
             Instruction::LoadSprite(x) => {
-                self.i = FONTSET_START_ADDRESS + u16::from(self.v[x as usize]) * 5;
+                self.i = FONTSET_START_ADDRESS + u16::from(self.v[x as usize] & 0x0F) * 5;
+            }
+            Instruction::LoadBigSprite(x) => {
+                self.i = BIG_FONTSET_START_ADDRESS + u16::from(self.v[x as usize] & 0x0F) * 10;
             }
             Instruction::Bcd(x) => {
                 let value = self.v[x as usize];
-                self.memory[self.i as usize] = value / 100;
-                self.memory[(self.i + 1) as usize] = (value % 100) / 10;
-                self.memory[(self.i + 2) as usize] = value % 10;
+                let range = self.mem_range(self.i as usize, 3)?;
+                for (addr, digit) in range.zip([value / 100, (value % 100) / 10, value % 10]) {
+                    self.write_memory(addr, digit);
+                }
             }
             Instruction::DumpRegs(x) => {
-                for idx in 0..=x {
-                    self.memory[(self.i + idx as u16) as usize] = self.v[idx as usize];
+                let count = usize::from(x) + 1;
+                let range = self.mem_range(self.i as usize, count)?;
+                for (addr, reg) in range.zip(0..count) {
+                    self.write_memory(addr, self.v[reg]);
                 }
+                self.increment_i_after_load_store(x);
             }
             Instruction::LoadRegs(x) => {
-                for idx in 0..=x {
-                    self.v[idx as usize] = self.memory[(self.i + idx as u16) as usize];
-                }
+                let count = usize::from(x) + 1;
+                let range = self.mem_range(self.i as usize, count)?;
+                self.v[..count].copy_from_slice(&self.memory[range]);
+                self.increment_i_after_load_store(x);
+            }
+            Instruction::StoreFlags(x) => {
+                let n = usize::from(x) + 1;
+                self.rpl_flags[..n].copy_from_slice(&self.v[..n]);
+            }
+            Instruction::LoadFlags(x) => {
+                let n = usize::from(x) + 1;
+                self.v[..n].copy_from_slice(&self.rpl_flags[..n]);
             }
             Instruction::NoOp => {}
         }
+        Ok(())
+    }
+
+    fn scroll_down(&mut self, n: u16) {
+        self.dirty_rows = all_rows_dirty();
+        let width = self.width();
+        for y in (0..self.height()).rev() {
+            for x in 0..width {
+                let index = (x + y * width) as usize;
+                let src = if y >= n {
+                    self.gfx[(x + (y - n) * width) as usize]
+                } else {
+                    0
+                };
+                self.gfx[index] = (self.gfx[index] & !self.planes) | (src & self.planes);
+            }
+        }
+    }
+
+    fn scroll_right(&mut self, n: u16) {
+        self.dirty_rows = all_rows_dirty();
+        let width = self.width();
+        for y in 0..self.height() {
+            for x in (0..width).rev() {
+                let index = (x + y * width) as usize;
+                let src = if x >= n {
+                    self.gfx[(x - n + y * width) as usize]
+                } else {
+                    0
+                };
+                self.gfx[index] = (self.gfx[index] & !self.planes) | (src & self.planes);
+            }
+        }
+    }
+
+    fn scroll_left(&mut self, n: u16) {
+        self.dirty_rows = all_rows_dirty();
+        let width = self.width();
+        for y in 0..self.height() {
+            for x in 0..width {
+                let index = (x + y * width) as usize;
+                let src = if x + n < width {
+                    self.gfx[(x + n + y * width) as usize]
+                } else {
+                    0
+                };
+                self.gfx[index] = (self.gfx[index] & !self.planes) | (src & self.planes);
+            }
+        }
+    }
+}
+
+/// Every row of the display, as marked for a full redraw
+fn all_rows_dirty() -> u64 {
+    u64::MAX
+}
+
+/// Registers VX through VY, in descending order when X > Y
+fn register_range(x: u8, y: u8) -> Box<dyn Iterator<Item = usize>> {
+    let (x, y) = (usize::from(x), usize::from(y));
+    if x <= y {
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
     }
 }
 
@@ -292,7 +866,7 @@ mod tests {
     use crate::instruction::Instruction;
 
     fn gen_test_chip8() -> (Chip8, Vec<u8>) {
-        let mut chip8 = Chip8::new();
+        let mut chip8 = Chip8::new(Quirks::default());
         let bytes = vec![
             // 0x00E0 CLS, 0x00EE RET
             0x00, 0xE0, 0x00, 0xEE, // 0x0nnn SYS addr, 0x1nnn JP addr, 0x2nnn CALL addr
@@ -312,7 +886,7 @@ mod tests {
             0xF1, 0x07, 0xF1, 0x0A, 0xF1, 0x15, 0xF1, 0x18, 0xF1, 0x1E, 0xF1, 0x29, 0xF1, 0x33,
             0xF1, 0x55, 0xF1, 0x65,
         ];
-        chip8.load_rom(&bytes);
+        chip8.load_rom(&bytes).unwrap();
         (chip8, bytes)
     }
 
@@ -320,47 +894,655 @@ mod tests {
     fn test_rom_loading() {
         let (chip8, bytes) = gen_test_chip8();
         let start = OPS_START_ADDRESS as usize;
-        let end = (start + bytes.len()) as usize;
+        let end = start + bytes.len();
         assert!(chip8.memory[start..end] == bytes);
     }
 
     #[test]
     fn test_opcode_parsing() {
         let (mut chip8, _) = gen_test_chip8();
-        assert!(chip8.pop_opcode() == Instruction::Cls);
-        assert!(chip8.pop_opcode() == Instruction::Ret);
-        assert!(chip8.pop_opcode() == Instruction::Sys(0x123));
-        assert!(chip8.pop_opcode() == Instruction::Jump(0x123));
-        assert!(chip8.pop_opcode() == Instruction::Call(0x123));
-        assert!(chip8.pop_opcode() == Instruction::SkipEqByte(0x1, 0x02));
-        assert!(chip8.pop_opcode() == Instruction::SkipNeByte(0x1, 0x02));
-        assert!(chip8.pop_opcode() == Instruction::SkipEqReg(0x1, 0x02));
-        assert!(chip8.pop_opcode() == Instruction::LoadByte(0x1, 0x02));
-        assert!(chip8.pop_opcode() == Instruction::AddByte(0x1, 0x02));
-        assert!(chip8.pop_opcode() == Instruction::LoadReg(0x1, 0x02));
-        assert!(chip8.pop_opcode() == Instruction::OrReg(0x1, 0x02));
-        assert!(chip8.pop_opcode() == Instruction::AndReg(0x1, 0x02));
-        assert!(chip8.pop_opcode() == Instruction::XorReg(0x1, 0x02));
-        assert!(chip8.pop_opcode() == Instruction::AddReg(0x1, 0x02));
-        assert!(chip8.pop_opcode() == Instruction::SubReg(0x1, 0x02));
-        assert!(chip8.pop_opcode() == Instruction::ShrReg(0x1, 0x02));
-        assert!(chip8.pop_opcode() == Instruction::SubnReg(0x1, 0x02));
-        assert!(chip8.pop_opcode() == Instruction::ShlReg(0x1, 0x02));
-        assert!(chip8.pop_opcode() == Instruction::SkipNeReg(0x1, 0x02));
-        assert!(chip8.pop_opcode() == Instruction::LoadI(0x123));
-        assert!(chip8.pop_opcode() == Instruction::JumpV0(0x123));
-        assert!(chip8.pop_opcode() == Instruction::Rand(0x1, 0x02));
-        assert!(chip8.pop_opcode() == Instruction::Draw(0x1, 0x2, 0x3));
-        assert!(chip8.pop_opcode() == Instruction::SkipIfKey(0x1));
-        assert!(chip8.pop_opcode() == Instruction::SkipIfNotKey(0x1));
-        assert!(chip8.pop_opcode() == Instruction::LoadDT(0x1));
-        assert!(chip8.pop_opcode() == Instruction::WaitKey(0x1));
-        assert!(chip8.pop_opcode() == Instruction::SetDT(0x1));
-        assert!(chip8.pop_opcode() == Instruction::SetST(0x1));
-        assert!(chip8.pop_opcode() == Instruction::AddI(0x1));
-        assert!(chip8.pop_opcode() == Instruction::LoadSprite(0x1));
-        assert!(chip8.pop_opcode() == Instruction::Bcd(0x1));
-        assert!(chip8.pop_opcode() == Instruction::DumpRegs(0x1));
-        assert!(chip8.pop_opcode() == Instruction::LoadRegs(0x1));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::Cls);
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::Ret);
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::Sys(0x123));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::Jump(0x123));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::Call(0x123));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::SkipEqByte(0x1, 0x02));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::SkipNeByte(0x1, 0x02));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::SkipEqReg(0x1, 0x02));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::LoadByte(0x1, 0x02));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::AddByte(0x1, 0x02));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::LoadReg(0x1, 0x02));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::OrReg(0x1, 0x02));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::AndReg(0x1, 0x02));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::XorReg(0x1, 0x02));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::AddReg(0x1, 0x02));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::SubReg(0x1, 0x02));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::ShrReg(0x1, 0x02));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::SubnReg(0x1, 0x02));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::ShlReg(0x1, 0x02));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::SkipNeReg(0x1, 0x02));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::LoadI(0x123));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::JumpV0(0x123));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::Rand(0x1, 0x02));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::Draw(0x1, 0x2, 0x3));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::SkipIfKey(0x1));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::SkipIfNotKey(0x1));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::LoadDT(0x1));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::WaitKey(0x1));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::SetDT(0x1));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::SetST(0x1));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::AddI(0x1));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::LoadSprite(0x1));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::Bcd(0x1));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::DumpRegs(0x1));
+        assert!(chip8.pop_opcode().unwrap().1 == Instruction::LoadRegs(0x1));
+    }
+
+    #[test]
+    fn test_timers_independent_of_instructions() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        // LD V0, 0x05; LD DT, V0; LD ST, V0; JP 0x206
+        chip8
+            .load_rom(&[0x60, 0x05, 0xF0, 0x15, 0xF0, 0x18, 0x12, 0x06])
+            .unwrap();
+        for _ in 0..3 {
+            chip8.tick().unwrap();
+        }
+        for _ in 0..10 {
+            chip8.tick().unwrap();
+        }
+        assert_eq!(chip8.delay_timer, 5);
+        assert_eq!(chip8.sound_timer, 5);
+        chip8.tick_timers();
+        assert_eq!(chip8.delay_timer, 4);
+        assert_eq!(chip8.sound_timer, 4);
+    }
+
+    #[test]
+    fn test_run_frame() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        // LD V0, 0x05; LD DT, V0; JP 0x204
+        chip8
+            .load_rom(&[0x60, 0x05, 0xF0, 0x15, 0x12, 0x04])
+            .unwrap();
+        chip8.run_frame(10).unwrap();
+        assert_eq!(chip8.delay_timer, 4);
+        chip8.run_frame(10).unwrap();
+        assert_eq!(chip8.delay_timer, 3);
+        assert_eq!(chip8.pc, 0x204);
+    }
+
+    #[test]
+    fn test_quirk_shift_uses_vy() {
+        // LD V1, 0x01; LD V2, 0x06; SHR V1, V2
+        let rom = [0x61, 0x01, 0x62, 0x06, 0x81, 0x26];
+        let mut vip = Chip8::new(Quirks::cosmac_vip());
+        vip.load_rom(&rom).unwrap();
+        vip.run_frame(3).unwrap();
+        assert_eq!(vip.v[1], 0x03);
+        assert_eq!(vip.v[0xF], 0);
+
+        let mut schip = Chip8::new(Quirks::superchip());
+        schip.load_rom(&rom).unwrap();
+        schip.run_frame(3).unwrap();
+        assert_eq!(schip.v[1], 0x00);
+        assert_eq!(schip.v[0xF], 1);
+    }
+
+    #[test]
+    fn test_quirk_load_store_increment() {
+        // LD I, 0x300; LD [I], V2
+        let rom = [0xA3, 0x00, 0xF2, 0x55];
+        let mut vip = Chip8::new(Quirks::cosmac_vip());
+        vip.load_rom(&rom).unwrap();
+        vip.run_frame(2).unwrap();
+        assert_eq!(vip.i, 0x303);
+
+        let mut schip = Chip8::new(Quirks::superchip());
+        schip.load_rom(&rom).unwrap();
+        schip.run_frame(2).unwrap();
+        assert_eq!(schip.i, 0x300);
+
+        // CHIP-48 adds X, one short of the VIP
+        assert_ne!(Quirks::chip48(), Quirks::superchip());
+        let mut chip48 = Chip8::new(Quirks::chip48());
+        chip48.load_rom(&rom).unwrap();
+        chip48.run_frame(2).unwrap();
+        assert_eq!(chip48.i, 0x302);
+
+        // LD I, 0x300; LD V2, [I]
+        let mut chip48 = Chip8::new(Quirks::chip48());
+        chip48.load_rom(&[0xA3, 0x00, 0xF2, 0x65]).unwrap();
+        chip48.run_frame(2).unwrap();
+        assert_eq!(chip48.i, 0x302);
+    }
+
+    #[test]
+    fn test_quirk_vf_reset() {
+        // LD VF, 0x01; OR V0, V1
+        let rom = [0x6F, 0x01, 0x80, 0x11];
+        let mut vip = Chip8::new(Quirks::cosmac_vip());
+        vip.load_rom(&rom).unwrap();
+        vip.run_frame(2).unwrap();
+        assert_eq!(vip.v[0xF], 0);
+
+        let mut schip = Chip8::new(Quirks::superchip());
+        schip.load_rom(&rom).unwrap();
+        schip.run_frame(2).unwrap();
+        assert_eq!(schip.v[0xF], 1);
+    }
+
+    #[test]
+    fn test_quirk_clipping() {
+        // LD V0, 62; LD I, 0x50 (font "0"); DRW V0, V0, 1
+        let rom = [0x60, 62, 0xA0, 0x50, 0xD0, 0x01];
+        let quirks = Quirks {
+            clipping: false,
+            ..Quirks::cosmac_vip()
+        };
+        let mut wrapping = Chip8::new(quirks);
+        wrapping.load_rom(&rom).unwrap();
+        wrapping.run_frame(3).unwrap();
+        assert_eq!(wrapping.pixel_at(63, 30), 1);
+        assert_eq!(wrapping.pixel_at(0, 30), 1);
+
+        let mut clipping = Chip8::new(Quirks::cosmac_vip());
+        clipping.load_rom(&rom).unwrap();
+        clipping.run_frame(3).unwrap();
+        assert_eq!(clipping.pixel_at(63, 30), 1);
+        assert_eq!(clipping.pixel_at(0, 30), 0);
+    }
+
+    #[test]
+    fn test_quirk_jump_uses_vx() {
+        // LD V0, 0x02; LD V3, 0x04; JP V0, 0x300
+        let rom = [0x60, 0x02, 0x63, 0x04, 0xB3, 0x00];
+        let mut vip = Chip8::new(Quirks::cosmac_vip());
+        vip.load_rom(&rom).unwrap();
+        vip.run_frame(3).unwrap();
+        assert_eq!(vip.pc, 0x302);
+
+        let mut schip = Chip8::new(Quirks::superchip());
+        schip.load_rom(&rom).unwrap();
+        schip.run_frame(3).unwrap();
+        assert_eq!(schip.pc, 0x304);
+    }
+
+    #[test]
+    fn test_quirk_display_wait() {
+        // DRW V0, V0, 1; LD V1, 0x01
+        let rom = [0xD0, 0x01, 0x61, 0x01];
+        let mut vip = Chip8::new(Quirks::cosmac_vip());
+        vip.load_rom(&rom).unwrap();
+        vip.run_frame(10).unwrap();
+        assert_eq!(vip.v[1], 0);
+        vip.run_frame(10).unwrap();
+        assert_eq!(vip.v[1], 1);
+
+        let mut schip = Chip8::new(Quirks::superchip());
+        schip.load_rom(&rom).unwrap();
+        schip.run_frame(10).unwrap();
+        assert_eq!(schip.v[1], 1);
+    }
+
+    #[test]
+    fn test_wait_key_on_press() {
+        // LD V1, K; JP 0x202
+        let rom = [0xF1, 0x0A, 0x12, 0x02];
+        let mut chip8 = Chip8::new(Quirks::superchip());
+        chip8.load_rom(&rom).unwrap();
+        // A key held before the wait does not complete it
+        chip8.keypress(0x3, true);
+        chip8.run_frame(10).unwrap();
+        assert_eq!(chip8.pc, 0x200);
+        chip8.keypress(0x3, false);
+        chip8.keypress(0x7, true);
+        chip8.run_frame(10).unwrap();
+        assert_eq!(chip8.pc, 0x202);
+        assert_eq!(chip8.v[1], 0x7);
+    }
+
+    #[test]
+    fn test_quirk_key_wait_release() {
+        // LD V0, 0x05; LD DT, V0; LD V1, K; JP 0x206
+        let rom = [0x60, 0x05, 0xF0, 0x15, 0xF1, 0x0A, 0x12, 0x06];
+        let mut chip8 = Chip8::new(Quirks::cosmac_vip());
+        chip8.load_rom(&rom).unwrap();
+        chip8.run_frame(10).unwrap();
+        assert_eq!(chip8.pc, 0x204);
+        assert_eq!(chip8.delay_timer, 5);
+
+        chip8.keypress(0xA, true);
+        chip8.run_frame(10).unwrap();
+        assert_eq!(chip8.pc, 0x204);
+        assert_eq!(chip8.delay_timer, 5);
+
+        chip8.keypress(0xA, false);
+        chip8.run_frame(10).unwrap();
+        assert_eq!(chip8.pc, 0x206);
+        assert_eq!(chip8.v[1], 0xA);
+        assert_eq!(chip8.delay_timer, 4);
+    }
+
+    #[test]
+    fn test_superchip_opcode_parsing() {
+        assert!(Instruction::from(0x00C3) == Instruction::ScrollDown(0x3));
+        assert!(Instruction::from(0x00FB) == Instruction::ScrollRight);
+        assert!(Instruction::from(0x00FC) == Instruction::ScrollLeft);
+        assert!(Instruction::from(0x00FD) == Instruction::Exit);
+        assert!(Instruction::from(0x00FE) == Instruction::Lores);
+        assert!(Instruction::from(0x00FF) == Instruction::Hires);
+        assert!(Instruction::from(0xD120) == Instruction::Draw(0x1, 0x2, 0x0));
+        assert!(Instruction::from(0xF130) == Instruction::LoadBigSprite(0x1));
+        assert!(Instruction::from(0xF175) == Instruction::StoreFlags(0x1));
+        assert!(Instruction::from(0xF185) == Instruction::LoadFlags(0x1));
+    }
+
+    #[test]
+    fn test_superchip_hires() {
+        let mut chip8 = Chip8::with_mode(Mode::SuperChip, Quirks::superchip());
+        // HIGH; LD V0, 120; LD V1, 60; LD I, 0x50 (font "0"); DRW V0, V1, 1
+        chip8
+            .load_rom(&[0x00, 0xFF, 0x60, 120, 0x61, 60, 0xA0, 0x50, 0xD0, 0x11])
+            .unwrap();
+        chip8.run_frame(5).unwrap();
+        assert_eq!((chip8.width(), chip8.height()), (128, 64));
+        assert_eq!(chip8.pixel_at(120, 60), 1);
+        assert_eq!(chip8.pixel_at(123, 60), 1);
+        assert_eq!(chip8.pixel_at(124, 60), 0);
+    }
+
+    #[test]
+    fn test_superchip_large_sprite() {
+        let mut chip8 = Chip8::with_mode(Mode::SuperChip, Quirks::superchip());
+        // HIGH; LD I, 0x300; DRW V0, V0, 0
+        chip8
+            .load_rom(&[0x00, 0xFF, 0xA3, 0x00, 0xD0, 0x00])
+            .unwrap();
+        chip8.memory[0x300..0x320].fill(0xFF);
+        chip8.run_frame(3).unwrap();
+        for y in 0..16 {
+            for x in 0..16 {
+                assert_eq!(chip8.pixel_at(x, y), 1);
+            }
+            assert_eq!(chip8.pixel_at(16, y), 0);
+        }
+        assert_eq!(chip8.pixel_at(0, 16), 0);
+    }
+
+    #[test]
+    fn test_superchip_scrolling() {
+        let mut chip8 = Chip8::with_mode(Mode::SuperChip, Quirks::superchip());
+        // HIGH; LD I, 0x50 (font "0"); DRW V0, V0, 1; SCD 2; SCR; SCL; SCL
+        chip8
+            .load_rom(&[
+                0x00, 0xFF, 0xA0, 0x50, 0xD0, 0x01, 0x00, 0xC2, 0x00, 0xFB, 0x00, 0xFC, 0x00, 0xFC,
+            ])
+            .unwrap();
+        chip8.run_frame(4).unwrap();
+        assert_eq!(chip8.pixel_at(0, 0), 0);
+        assert_eq!(chip8.pixel_at(0, 2), 1);
+        chip8.run_frame(1).unwrap();
+        assert_eq!(chip8.pixel_at(0, 2), 0);
+        assert_eq!(chip8.pixel_at(4, 2), 1);
+        assert_eq!(chip8.pixel_at(7, 2), 1);
+        chip8.run_frame(2).unwrap();
+        assert_eq!(chip8.pixel_at(0, 2), 0);
+        assert_eq!(chip8.pixel_at(127, 2), 0);
+    }
+
+    #[test]
+    fn test_superchip_big_font_and_flags() {
+        let mut chip8 = Chip8::with_mode(Mode::SuperChip, Quirks::superchip());
+        // LD V0, 0x02; LD HF, V0; LD V1, 0x07; LD R, V1; LD V0, 0; LD V1, 0; LD V, R1; EXIT
+        chip8
+            .load_rom(&[
+                0x60, 0x02, 0xF0, 0x30, 0x61, 0x07, 0xF1, 0x75, 0x60, 0x00, 0x61, 0x00, 0xF1, 0x85,
+                0x00, 0xFD, 0x60, 0xFF,
+            ])
+            .unwrap();
+        chip8.run_frame(20).unwrap();
+        assert_eq!(chip8.i, BIG_FONTSET_START_ADDRESS + 20);
+        assert_eq!(chip8.v[0], 0x02);
+        assert_eq!(chip8.v[1], 0x07);
+        assert!(chip8.is_halted());
+        assert_eq!(chip8.pc, 0x210);
+    }
+
+    #[test]
+    fn test_load_sprite_uses_low_nibble() {
+        let mut chip8 = Chip8::new(Quirks::default());
+        // LD V0, 0x1A; LD F, V0
+        chip8.load_rom(&[0x60, 0x1A, 0xF0, 0x29]).unwrap();
+        chip8.run_frame(2).unwrap();
+        assert_eq!(chip8.i, FONTSET_START_ADDRESS + 0xA * 5);
+    }
+
+    #[test]
+    fn test_superchip_instructions_ignored_in_chip8_mode() {
+        let mut chip8 = Chip8::new(Quirks::cosmac_vip());
+        // HIGH; EXIT; LD V0, 0x01
+        chip8
+            .load_rom(&[0x00, 0xFF, 0x00, 0xFD, 0x60, 0x01])
+            .unwrap();
+        chip8.run_frame(3).unwrap();
+        assert_eq!((chip8.width(), chip8.height()), (64, 32));
+        assert!(!chip8.is_halted());
+        assert_eq!(chip8.v[0], 0x01);
+    }
+
+    #[test]
+    fn test_xochip_opcode_parsing() {
+        assert!(Instruction::from(0x5122) == Instruction::SaveRange(0x1, 0x2));
+        assert!(Instruction::from(0x5123) == Instruction::LoadRange(0x1, 0x2));
+        assert!(Instruction::from(0xF000) == Instruction::LoadILong);
+        assert!(Instruction::from(0xF201) == Instruction::Plane(0x2));
+        assert!(Instruction::from(0xF002) == Instruction::AudioPattern);
+        assert!(Instruction::from(0xF13A) == Instruction::Pitch(0x1));
+        assert_eq!(Instruction::LoadILong.size(), 4);
+        assert_eq!(Instruction::Cls.size(), 2);
+    }
+
+    #[test]
+    fn test_xochip_long_load_and_skip() {
+        let mut chip8 = Chip8::with_mode(Mode::XoChip, Quirks::xochip());
+        // SE V0, 0x00; LD I, long 0x1234; LD I, long 0xABCD; LD V1, 0x01
+        chip8
+            .load_rom(&[
+                0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0xF0, 0x00, 0xAB, 0xCD, 0x61, 0x01,
+            ])
+            .unwrap();
+        chip8.run_frame(3).unwrap();
+        assert_eq!(chip8.i, 0xABCD);
+        assert_eq!(chip8.v[1], 0x01);
+        assert_eq!(chip8.memory.len(), 0x10000);
+    }
+
+    #[test]
+    fn test_xochip_planes() {
+        let mut chip8 = Chip8::with_mode(Mode::XoChip, Quirks::xochip());
+        // PLANE 3; LD I, 0x300; DRW V0, V0, 1; PLANE 1; CLS
+        chip8
+            .load_rom(&[0xF3, 0x01, 0xA3, 0x00, 0xD0, 0x01, 0xF1, 0x01, 0x00, 0xE0])
+            .unwrap();
+        // First plane lights pixels 0-3, second plane pixels 2-5
+        chip8.memory[0x300] = 0xF0;
+        chip8.memory[0x301] = 0x3C;
+        chip8.run_frame(3).unwrap();
+        assert_eq!(chip8.pixel_at(0, 0), 1);
+        assert_eq!(chip8.pixel_at(2, 0), 3);
+        assert_eq!(chip8.pixel_at(4, 0), 2);
+        assert_eq!(chip8.pixel_at(6, 0), 0);
+        chip8.run_frame(2).unwrap();
+        assert_eq!(chip8.pixel_at(0, 0), 0);
+        assert_eq!(chip8.pixel_at(2, 0), 2);
+        assert_eq!(chip8.pixel_at(4, 0), 2);
+    }
+
+    #[test]
+    fn test_xochip_register_ranges() {
+        let mut chip8 = Chip8::with_mode(Mode::XoChip, Quirks::xochip());
+        // LD V2, 0x22; LD V3, 0x33; LD I, 0x300; SAVE V2 - V3; LOAD V5 - V4
+        chip8
+            .load_rom(&[0x62, 0x22, 0x63, 0x33, 0xA3, 0x00, 0x52, 0x32, 0x55, 0x43])
+            .unwrap();
+        chip8.run_frame(5).unwrap();
+        assert_eq!(&chip8.memory[0x300..0x302], &[0x22, 0x33]);
+        assert_eq!(chip8.v[5], 0x22);
+        assert_eq!(chip8.v[4], 0x33);
+        assert_eq!(chip8.i, 0x300);
+    }
+
+    #[test]
+    fn test_xochip_audio() {
+        let mut chip8 = Chip8::with_mode(Mode::XoChip, Quirks::xochip());
+        // LD I, 0x300; AUDIO; LD V0, 112; PITCH V0
+        chip8
+            .load_rom(&[0xA3, 0x00, 0xF0, 0x02, 0x60, 112, 0xF0, 0x3A])
+            .unwrap();
+        chip8.memory[0x300..0x310].copy_from_slice(&[0xAA; 16]);
+        assert_eq!(chip8.pattern_rate(), 4000.0);
+        chip8.run_frame(4).unwrap();
+        assert_eq!(chip8.audio_pattern(), &[0xAA; 16]);
+        assert_eq!(chip8.pattern_rate(), 8000.0);
+    }
+
+    #[test]
+    fn test_rom_too_large() {
+        let mut chip8 = Chip8::default();
+        assert!(chip8.load_rom(&[0; 0xE00]).is_ok());
+        assert_eq!(
+            chip8.load_rom(&[0; 0xE01]),
+            Err(Chip8Error::RomTooLarge {
+                size: 0xE01,
+                max: 0xE00
+            })
+        );
+    }
+
+    #[test]
+    fn test_stack_errors() {
+        let mut chip8 = Chip8::default();
+        // RET
+        chip8.load_rom(&[0x00, 0xEE]).unwrap();
+        assert_eq!(chip8.tick(), Err(Chip8Error::StackUnderflow { pc: 0x200 }));
+
+        let mut chip8 = Chip8::default();
+        // CALL 0x200
+        chip8.load_rom(&[0x22, 0x00]).unwrap();
+        for _ in 0..16 {
+            chip8.tick().unwrap();
+        }
+        assert_eq!(chip8.tick(), Err(Chip8Error::StackOverflow { pc: 0x200 }));
+    }
+
+    #[test]
+    fn test_memory_out_of_bounds() {
+        let mut chip8 = Chip8::default();
+        // LD I, 0xFFE; LD B, V0
+        chip8.load_rom(&[0xAF, 0xFE, 0xF0, 0x33]).unwrap();
+        chip8.tick().unwrap();
+        assert_eq!(
+            chip8.tick(),
+            Err(Chip8Error::MemoryOutOfBounds {
+                addr: 0x1000,
+                pc: 0x202
+            })
+        );
+
+        let mut chip8 = Chip8::default();
+        // JP 0xFFF
+        chip8.load_rom(&[0x1F, 0xFF]).unwrap();
+        chip8.tick().unwrap();
+        assert_eq!(
+            chip8.tick(),
+            Err(Chip8Error::MemoryOutOfBounds {
+                addr: 0x1000,
+                pc: 0xFFF
+            })
+        );
+    }
+
+    #[test]
+    fn test_invalid_opcode() {
+        let mut chip8 = Chip8::default();
+        chip8.load_rom(&[0xFF, 0xFF]).unwrap();
+        assert_eq!(
+            chip8.tick(),
+            Err(Chip8Error::InvalidOpcode {
+                opcode: 0xFFFF,
+                pc: 0x200
+            })
+        );
+    }
+
+    #[test]
+    fn test_seeded_rand_is_reproducible() {
+        // RND V0, 0xFF; LD I, 0x300; ADD I, V0; LD [I], V0; JP 0x200
+        let rom = [0xC0, 0xFF, 0xA3, 0x00, 0xF0, 0x1E, 0xF0, 0x55, 0x12, 0x00];
+        let run = |seed| {
+            let mut chip8 = Chip8::with_rng(
+                Mode::Chip8,
+                Quirks::default(),
+                Box::new(Xorshift64::new(seed)),
+            );
+            chip8.load_rom(&rom).unwrap();
+            for _ in 0..20 {
+                chip8.run_frame(10).unwrap();
+            }
+            chip8
+        };
+        let a = run(42);
+        let b = run(42);
+        let c = run(43);
+        assert_eq!(a.v, b.v);
+        assert_eq!(a.memory, b.memory);
+        assert_ne!(a.memory, c.memory);
+    }
+
+    #[test]
+    fn test_observer() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        #[derive(Debug, Default)]
+        struct Recorder(Rc<RefCell<Vec<String>>>);
+
+        impl Observer for Recorder {
+            fn on_instruction(&mut self, pc: u16, ins: &Instruction) {
+                self.0.borrow_mut().push(format!("{:03X} {}", pc, ins));
+            }
+            fn on_memory_write(&mut self, addr: u16, old: u8, new: u8) {
+                self.0
+                    .borrow_mut()
+                    .push(format!("write {:03X} {} {}", addr, old, new));
+            }
+            fn on_draw(&mut self, x: u16, y: u16, rows: u16, collision: bool) {
+                self.0
+                    .borrow_mut()
+                    .push(format!("draw {} {} {} {}", x, y, rows, collision));
+            }
+            fn on_timer_set(&mut self, timer: Timer, value: u8) {
+                self.0.borrow_mut().push(format!("{:?} {}", timer, value));
+            }
+            fn on_unknown_opcode(&mut self, pc: u16, opcode: u16) {
+                self.0
+                    .borrow_mut()
+                    .push(format!("unknown {:03X} {:04X}", pc, opcode));
+            }
+        }
+
+        // LD V0, 42; LD I, 0x300; LD B, V0; LD DT, V0; DRW V0, V0, 1; 0xFFFF
+        let rom = [
+            0x60, 0x2A, 0xA3, 0x00, 0xF0, 0x33, 0xF0, 0x15, 0xD0, 0x01, 0xFF, 0xFF,
+        ];
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut chip8 = Chip8::new(Quirks::chip48());
+        chip8.set_observer(Box::new(Recorder(events.clone())));
+        chip8.load_rom(&rom).unwrap();
+        assert!(chip8.run_frame(10).is_err());
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                "200 LD V0, 0x2A",
+                "202 LD I, 0x300",
+                "204 LD B, V0",
+                "write 300 0 0",
+                "write 301 0 4",
+                "write 302 0 2",
+                "206 LD DT, V0",
+                "Delay 42",
+                "208 DRW V0, V0, 0x1",
+                "draw 42 10 1 false",
+                "unknown 20A FFFF",
+            ]
+        );
+
+        // Without an observer nothing is recorded
+        chip8.remove_observer();
+        chip8.pc = 0x200;
+        chip8.run_frame(2).unwrap();
+        assert_eq!(events.borrow().len(), 11);
+    }
+
+    #[test]
+    fn test_framebuffer_and_dirty_rows() {
+        // LD V0, 2; LD V1, 3; LD F, V0; DRW V1, V0, 5; CLS
+        let rom = [0x60, 0x02, 0x61, 0x03, 0xF0, 0x29, 0xD1, 0x05, 0x00, 0xE0];
+        let mut chip8 = Chip8::new(Quirks::chip48());
+        chip8.load_rom(&rom).unwrap();
+        // A new interpreter needs a full redraw
+        assert!(chip8.frame_dirty());
+        assert!(!chip8.frame_dirty());
+
+        for _ in 0..4 {
+            chip8.tick().unwrap();
+        }
+        assert_eq!(
+            chip8.take_dirty_rows().collect::<Vec<_>>(),
+            vec![2, 3, 4, 5, 6]
+        );
+        assert!(!chip8.frame_dirty());
+
+        // The digit 2 is 0xF0, 0x10, 0xF0, 0x80, 0xF0 drawn 3 pixels in
+        assert_eq!(chip8.framebuffer().len(), 64 * 32);
+        assert_eq!(
+            &chip8.framebuffer()[2 * 64..2 * 64 + 8],
+            &[0, 0, 0, 1, 1, 1, 1, 0]
+        );
+        let packed = chip8.framebuffer_1bpp();
+        assert_eq!(packed.len(), 64 * 32 / 8);
+        assert_eq!(packed[2 * 8], 0x1E);
+        assert_eq!(packed[3 * 8], 0x02);
+
+        chip8.tick().unwrap();
+        assert_eq!(chip8.take_dirty_rows().count(), 32);
+        assert!(chip8.framebuffer_1bpp().iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn test_decode_cache_self_modifying() {
+        // LD V1, 0x42; LD V0, 0x61; LD V1, 0x2A; LD I, 0x200; LD [I], V1
+        // (overwriting the first instruction with LD V1, 0x2A); LD V1, 0x00;
+        // JP 0x200
+        let rom = [
+            0x61, 0x42, 0x60, 0x61, 0x61, 0x2A, 0xA2, 0x00, 0xF1, 0x55, 0x61, 0x00, 0x12, 0x00,
+        ];
+        let mut chip8 = Chip8::default();
+        chip8.set_decode_cache(true);
+        chip8.load_rom(&rom).unwrap();
+        chip8.tick().unwrap();
+        assert_eq!(chip8.v[1], 0x42);
+        for _ in 0..7 {
+            chip8.tick().unwrap();
+        }
+        assert_eq!(chip8.pc, 0x202);
+        assert_eq!(chip8.v[1], 0x2A);
+
+        // Pokes and reloading the ROM invalidate too
+        chip8.poke(0x201, 0x07).unwrap();
+        chip8.set_pc(0x200);
+        chip8.tick().unwrap();
+        assert_eq!(chip8.v[1], 0x07);
+        chip8.load_rom(&rom).unwrap();
+        chip8.set_pc(0x200);
+        chip8.tick().unwrap();
+        assert_eq!(chip8.v[1], 0x42);
+    }
+
+    #[test]
+    fn test_decode_cache_matches_uncached() {
+        let rom = include_bytes!("../../examples/tetris.ch8");
+        let run = |cached: bool| {
+            let mut chip8 = Chip8::default();
+            chip8.set_decode_cache(cached);
+            chip8.load_rom(rom).unwrap();
+            for frame in 0..600 {
+                chip8.keypress(0x5, frame % 50 < 10);
+                chip8.run_frame(10).unwrap();
+            }
+            chip8.save_state()
+        };
+        assert_eq!(run(true), run(false));
     }
 }
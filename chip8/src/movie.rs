@@ -0,0 +1,380 @@
+//! Input movies: the keys held on every frame of a session, replayed to
+//! reproduce it exactly.
+
+use std::fmt;
+
+use crate::sha1::sha1;
+use crate::state::{fnv1a, quirks_from_bits, quirks_to_bits};
+use crate::{Chip8, Chip8Error, Mode, Quirks, Xorshift64};
+
+const MAGIC: &[u8; 4] = b"C8MV";
+
+/// Version of the movie format written by [`Movie::to_bytes`]
+pub const MOVIE_VERSION: u16 = 1;
+
+/// Size of everything before the frames
+const HEADER_SIZE: usize = 44;
+
+/// A recorded session: what it ran and the keypad on each frame.
+///
+/// Two interpreters started by [`Movie::chip8`] and fed the same frames by
+/// [`MoviePlayer`] run identically, as 0xCXNN draws from a random source
+/// seeded with [`Movie::seed`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    /// SHA-1 of the ROM
+    pub rom_sha1: [u8; 20],
+    pub seed: u64,
+    pub mode: Mode,
+    pub quirks: Quirks,
+    pub instructions_per_frame: u32,
+    /// Keys held during each frame, one bit per key
+    pub frames: Vec<u16>,
+}
+
+impl Movie {
+    /// A movie without frames of `rom` run with these settings
+    pub fn new(
+        rom: &[u8],
+        seed: u64,
+        mode: Mode,
+        quirks: Quirks,
+        instructions_per_frame: u32,
+    ) -> Self {
+        Movie {
+            rom_sha1: sha1(rom),
+            seed,
+            mode,
+            quirks,
+            instructions_per_frame,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Whether the movie was recorded with `rom`
+    pub fn matches_rom(&self, rom: &[u8]) -> bool {
+        sha1(rom) == self.rom_sha1
+    }
+
+    /// A fresh interpreter with `rom` loaded, set up as the recorded one was
+    pub fn chip8(&self, rom: &[u8]) -> Result<Chip8, MovieError> {
+        if !self.matches_rom(rom) {
+            return Err(MovieError::RomMismatch);
+        }
+        let rng = Box::new(Xorshift64::new(self.seed));
+        let mut chip8 = Chip8::with_rng(self.mode, self.quirks, rng);
+        chip8.load_rom(rom).map_err(MovieError::Rom)?;
+        Ok(chip8)
+    }
+
+    /// Encodes the movie as a little-endian blob laid out as follows:
+    ///
+    /// | Size  | Field                                                  |
+    /// |-------|--------------------------------------------------------|
+    /// | 4     | Magic bytes `C8MV`                                     |
+    /// | 2     | Format version, [`MOVIE_VERSION`]                      |
+    /// | 1     | Mode: 0 = CHIP-8, 1 = SUPER-CHIP, 2 = XO-CHIP          |
+    /// | 1     | Quirks, as in save states                              |
+    /// | 8     | Random seed                                            |
+    /// | 4     | Instructions per frame                                 |
+    /// | 20    | SHA-1 of the ROM                                       |
+    /// | 4     | Number of frames N                                     |
+    /// | 2 * N | Keys held during each frame, one bit per key           |
+    /// | 4     | FNV-1a checksum of all the preceding bytes             |
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_SIZE + self.frames.len() * 2 + 4);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&MOVIE_VERSION.to_le_bytes());
+        out.push(match self.mode {
+            Mode::Chip8 => 0,
+            Mode::SuperChip => 1,
+            Mode::XoChip => 2,
+        });
+        out.push(quirks_to_bits(&self.quirks));
+        out.extend_from_slice(&self.seed.to_le_bytes());
+        out.extend_from_slice(&self.instructions_per_frame.to_le_bytes());
+        out.extend_from_slice(&self.rom_sha1);
+        out.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        for keys in &self.frames {
+            out.extend_from_slice(&keys.to_le_bytes());
+        }
+        let checksum = fnv1a(&out);
+        out.extend_from_slice(&checksum.to_le_bytes());
+        out
+    }
+
+    /// Decodes a movie written by [`Movie::to_bytes`]
+    pub fn from_bytes(data: &[u8]) -> Result<Self, MovieError> {
+        if !data.starts_with(MAGIC) {
+            return Err(MovieError::BadMagic);
+        }
+        if data.len() < HEADER_SIZE + 4 {
+            return Err(MovieError::Truncated);
+        }
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        let frame_count = u32::from_le_bytes(data[40..44].try_into().unwrap()) as usize;
+        let payload_len = frame_count
+            .checked_mul(2)
+            .and_then(|len| len.checked_add(HEADER_SIZE))
+            .ok_or(MovieError::Truncated)?;
+        if data.len() < payload_len + 4 {
+            return Err(MovieError::Truncated);
+        }
+        if data.len() > payload_len + 4 {
+            return Err(MovieError::InvalidField("trailing data"));
+        }
+        let (payload, checksum) = data.split_at(payload_len);
+        if fnv1a(payload) != u32::from_le_bytes(checksum.try_into().unwrap()) {
+            return Err(MovieError::ChecksumMismatch);
+        }
+
+        let mode = match data[6] {
+            0 => Mode::Chip8,
+            1 => Mode::SuperChip,
+            2 => Mode::XoChip,
+            _ => return Err(MovieError::InvalidField("mode")),
+        };
+        Ok(Movie {
+            rom_sha1: data[20..40].try_into().unwrap(),
+            seed: u64::from_le_bytes(data[8..16].try_into().unwrap()),
+            mode,
+            quirks: quirks_from_bits(data[7]),
+            instructions_per_frame: u32::from_le_bytes(data[16..20].try_into().unwrap()),
+            frames: payload[HEADER_SIZE..]
+                .chunks_exact(2)
+                .map(|keys| u16::from_le_bytes([keys[0], keys[1]]))
+                .collect(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieError {
+    /// The blob does not start with the movie magic bytes
+    BadMagic,
+    /// The blob was written by an incompatible version of the format
+    UnsupportedVersion(u16),
+    /// The blob ended before all fields were read
+    Truncated,
+    /// The checksum does not match the contents of the blob
+    ChecksumMismatch,
+    /// A field holds a value no movie is written with
+    InvalidField(&'static str),
+    /// The movie was recorded with a different ROM
+    RomMismatch,
+    /// The ROM could not be loaded
+    Rom(Chip8Error),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::BadMagic => write!(f, "not an input movie"),
+            MovieError::UnsupportedVersion(version) => {
+                write!(f, "unsupported input movie version {}", version)
+            }
+            MovieError::Truncated => write!(f, "input movie is truncated"),
+            MovieError::ChecksumMismatch => write!(f, "input movie checksum mismatch"),
+            MovieError::InvalidField(field) => write!(f, "invalid input movie field: {}", field),
+            MovieError::RomMismatch => write!(f, "input movie was recorded with a different ROM"),
+            MovieError::Rom(err) => write!(f, "failed to load ROM: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MovieError::Rom(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Presses and releases keys so that exactly `keys` are held
+fn apply_keys(chip8: &mut Chip8, keys: u16) {
+    for key in 0..16 {
+        let pressed = keys & 1 << key != 0;
+        if (chip8.keypad[key] != 0) != pressed {
+            chip8.keypress(key, pressed);
+        }
+    }
+}
+
+/// Records the keys of a session into a [`Movie`].
+///
+/// Key changes are held back until the next frame starts, so a frame sees
+/// the same keys live as on replay. A key pressed and released between two
+/// frames is lost.
+#[derive(Debug)]
+pub struct MovieRecorder {
+    movie: Movie,
+    keys: u16,
+}
+
+impl MovieRecorder {
+    /// Appends to the frames of `movie`
+    pub fn new(movie: Movie) -> Self {
+        let keys = movie.frames.last().copied().unwrap_or(0);
+        MovieRecorder { movie, keys }
+    }
+
+    /// Sets the state of `key` from the next frame on, in place of
+    /// [`Chip8::keypress`]
+    pub fn keypress(&mut self, key: usize, pressed: bool) {
+        if key < 16 {
+            if pressed {
+                self.keys |= 1 << key;
+            } else {
+                self.keys &= !(1 << key);
+            }
+        }
+    }
+
+    /// Records the held keys and passes them to `chip8`. Call before running
+    /// each frame.
+    pub fn begin_frame(&mut self, chip8: &mut Chip8) {
+        self.movie.frames.push(self.keys);
+        apply_keys(chip8, self.keys);
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+/// Feeds the keys of a [`Movie`] to an interpreter, frame by frame
+#[derive(Debug)]
+pub struct MoviePlayer {
+    movie: Movie,
+    frame: usize,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> Self {
+        MoviePlayer { movie, frame: 0 }
+    }
+
+    /// Passes the keys of the next frame to `chip8`. Call before running
+    /// each frame. Returns false once all frames have been played, leaving
+    /// the keys as they were.
+    pub fn begin_frame(&mut self, chip8: &mut Chip8) -> bool {
+        let Some(&keys) = self.movie.frames.get(self.frame) else {
+            return false;
+        };
+        apply_keys(chip8, keys);
+        self.frame += 1;
+        true
+    }
+
+    /// Plays all remaining frames, running each for the recorded number of
+    /// instructions
+    pub fn run(&mut self, chip8: &mut Chip8) -> Result<(), Chip8Error> {
+        while self.begin_frame(chip8) {
+            chip8.run_frame(self.movie.instructions_per_frame)?;
+        }
+        Ok(())
+    }
+
+    /// Number of frames played so far
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.movie.frames.len()
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RandomSource;
+
+    const TETRIS: &[u8] = include_bytes!("../../examples/tetris.ch8");
+
+    /// Plays tetris for `frames` frames, mashing keys chosen by `seed`
+    fn record_tetris(frames: u32, seed: u64) -> (Movie, Chip8) {
+        let movie = Movie::new(TETRIS, 1234, Mode::Chip8, Quirks::cosmac_vip(), 15);
+        let mut chip8 = movie.chip8(TETRIS).unwrap();
+        let mut recorder = MovieRecorder::new(movie);
+        let mut player = Xorshift64::new(seed);
+        for _ in 0..frames {
+            // Several changes between frames, some of which cancel out
+            for _ in 0..3 {
+                let byte = player.next_byte();
+                recorder.keypress(usize::from(byte & 0xF), byte & 0x10 != 0);
+            }
+            recorder.begin_frame(&mut chip8);
+            chip8.run_frame(15).unwrap();
+        }
+        (recorder.finish(), chip8)
+    }
+
+    #[test]
+    fn test_replay_is_identical() {
+        let (movie, recorded) = record_tetris(900, 99);
+        assert_eq!(movie.frames.len(), 900);
+        assert!(movie.frames.iter().any(|&keys| keys != 0));
+
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        let mut replayed = movie.chip8(TETRIS).unwrap();
+        let mut player = MoviePlayer::new(movie);
+        player.run(&mut replayed).unwrap();
+        assert!(player.is_finished());
+        assert_eq!(player.frame(), 900);
+        assert_eq!(replayed.save_state(), recorded.save_state());
+
+        // Other inputs lead somewhere else
+        let (_, other) = record_tetris(900, 100);
+        assert_ne!(other.save_state(), recorded.save_state());
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let mut movie = Movie::new(&[0x12, 0x00], 7, Mode::XoChip, Quirks::xochip(), 1000);
+        movie.frames = vec![0, 0x8001, 0xFFFF];
+        let bytes = movie.to_bytes();
+        assert_eq!(bytes.len(), HEADER_SIZE + 6 + 4);
+        assert_eq!(Movie::from_bytes(&bytes), Ok(movie));
+    }
+
+    #[test]
+    fn test_errors() {
+        let movie = Movie::new(TETRIS, 0, Mode::Chip8, Quirks::default(), 10);
+        assert_eq!(
+            movie.chip8(&TETRIS[1..]).unwrap_err(),
+            MovieError::RomMismatch
+        );
+
+        let bytes = movie.to_bytes();
+        assert_eq!(Movie::from_bytes(b"C8ST"), Err(MovieError::BadMagic));
+        assert_eq!(
+            Movie::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(MovieError::Truncated)
+        );
+        let mut corrupt = bytes.clone();
+        corrupt[10] ^= 1;
+        assert_eq!(
+            Movie::from_bytes(&corrupt),
+            Err(MovieError::ChecksumMismatch)
+        );
+        let mut newer = bytes;
+        newer[4] = 2;
+        assert_eq!(
+            Movie::from_bytes(&newer),
+            Err(MovieError::UnsupportedVersion(2))
+        );
+    }
+}
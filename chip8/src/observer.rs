@@ -0,0 +1,31 @@
+use std::fmt;
+
+use crate::instruction::Instruction;
+
+/// Timer written by 0xFX15 or 0xFX18
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timer {
+    Delay,
+    Sound,
+}
+
+/// Callbacks for events during execution, installed with
+/// [`Chip8::set_observer`](crate::Chip8::set_observer). Every callback
+/// defaults to doing nothing, so observers only implement what they need.
+pub trait Observer: fmt::Debug {
+    /// Called before the instruction at `pc` is executed
+    fn on_instruction(&mut self, _pc: u16, _ins: &Instruction) {}
+
+    /// Called for every byte an instruction writes to memory
+    fn on_memory_write(&mut self, _addr: u16, _old: u8, _new: u8) {}
+
+    /// Called after a sprite of `rows` rows was drawn at (x, y)
+    fn on_draw(&mut self, _x: u16, _y: u16, _rows: u16, _collision: bool) {}
+
+    /// Called when a program sets a timer, not when it counts down
+    fn on_timer_set(&mut self, _timer: Timer, _value: u8) {}
+
+    /// Called before [`Chip8Error::InvalidOpcode`](crate::Chip8Error::InvalidOpcode)
+    /// is returned for `opcode` at `pc`
+    fn on_unknown_opcode(&mut self, _pc: u16, _opcode: u16) {}
+}
@@ -0,0 +1,1098 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::OPS_START_ADDRESS;
+use crate::assembler::{AssembleError, AssembleErrorKind};
+use crate::instruction::Instruction;
+
+/// Macro expansions allowed in one program before giving up on recursion
+const MAX_EXPANSIONS: usize = 10_000;
+
+/// A compiled Octo program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OctoProgram {
+    /// Bytes to load at 0x200
+    pub rom: Vec<u8>,
+    /// Address of every label in the source
+    pub labels: BTreeMap<String, u16>,
+}
+
+impl OctoProgram {
+    /// Name of a label at `addr`, the alphabetically first if there are several
+    pub fn label_at(&self, addr: u16) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(_, label)| **label == addr)
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+/// Compiles Octo source into a ROM loaded at 0x200.
+///
+/// Supported are labels, `:const`, `:alias`, `:calc`, `:macro`, `:org`,
+/// `:byte`, `:call`, `:unpack`, register and `i` assignments, `if ... then`,
+/// `if ... begin ... else ... end`, `loop ... while ... again` and all
+/// CHIP-8, SUPER-CHIP and XO-CHIP statements that map to a single
+/// instruction. Conditions comparing with `<`, `>`, `<=` and `>=` overwrite
+/// `vf`. `scroll-up`, `:stringmode`, `:next`, `:assert` and `:include` are
+/// not supported.
+///
+/// Execution starts at `main`. When `: main` is not the first thing in the
+/// source a `jump main` is placed at 0x200, as Octo does. `:calc`
+/// expressions use integer arithmetic and, like Octo, evaluate right to left
+/// without operator precedence.
+pub fn compile_octo(source: &str) -> Result<OctoProgram, AssembleError> {
+    let mut compiler = Compiler {
+        here: usize::from(OPS_START_ADDRESS),
+        ..Compiler::default()
+    };
+    let tokens = tokenize(source);
+    compiler.end = tokens
+        .last()
+        .map_or((1, 1), |token| (token.line, token.column));
+    compiler.tokens = tokens.into_iter().rev().collect();
+    compiler.compile()?;
+    Ok(OctoProgram {
+        rom: compiler.rom,
+        labels: compiler.labels,
+    })
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Debug)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum FixupKind {
+    /// The low 12 bits of the opcode at the address
+    Addr12,
+    /// The whole word at the address
+    Addr16,
+    /// The bytes of `v0 := NN` and `v1 := NN` written by `:unpack`, with
+    /// the nibble placed above the address
+    Unpack(u8),
+}
+
+/// A reference to a label that was not defined yet when it was compiled
+#[derive(Debug)]
+struct Fixup {
+    addr: usize,
+    kind: FixupKind,
+    token: Token,
+}
+
+#[derive(Debug)]
+enum Block {
+    /// `if ... begin`, with the address of the jump that skips the branch
+    If { token: Token, jump: usize },
+    /// `loop`, with the addresses of the jumps emitted by `while`
+    Loop {
+        token: Token,
+        start: usize,
+        breaks: Vec<usize>,
+    },
+}
+
+#[derive(Debug, Default)]
+struct Compiler {
+    /// Tokens left to compile, in reverse order so the next one is last
+    tokens: Vec<Token>,
+    /// Line and column of the last token, for errors at the end of the source
+    end: (usize, usize),
+    rom: Vec<u8>,
+    here: usize,
+    labels: BTreeMap<String, u16>,
+    constants: HashMap<String, i64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    expansions: usize,
+}
+
+fn error(token: &Token, kind: AssembleErrorKind) -> AssembleError {
+    AssembleError {
+        file: None,
+        line: token.line,
+        column: token.column,
+        kind,
+    }
+}
+
+fn unexpected(token: &Token) -> AssembleError {
+    error(
+        token,
+        AssembleErrorKind::UnexpectedToken(token.text.clone()),
+    )
+}
+
+impl Compiler {
+    fn compile(&mut self) -> Result<(), AssembleError> {
+        let starts_with_main = matches!(
+            self.tokens.as_slice(),
+            [.., main, colon] if colon.text == ":" && main.text == "main"
+        );
+        if !starts_with_main {
+            let main = Token {
+                text: "main".to_string(),
+                line: 1,
+                column: 1,
+            };
+            self.address(&main, self.here, FixupKind::Addr12)?;
+            self.emit(Instruction::Jump(0), &main)?;
+        }
+
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+        if let Some(block) = self.blocks.first() {
+            let token = match block {
+                Block::If { token, .. } | Block::Loop { token, .. } => token,
+            };
+            return Err(error(
+                token,
+                AssembleErrorKind::Unclosed(token.text.clone()),
+            ));
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let value = self
+                .labels
+                .get(&fixup.token.text)
+                .map(|addr| i64::from(*addr))
+                .or_else(|| self.constants.get(&fixup.token.text).copied());
+            let Some(value) = value else {
+                let kind = AssembleErrorKind::UndefinedSymbol(fixup.token.text.clone());
+                return Err(error(&fixup.token, kind));
+            };
+            self.patch(fixup.addr, fixup.kind, value, &fixup.token)?;
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<Token, AssembleError> {
+        self.tokens.pop().ok_or(AssembleError {
+            file: None,
+            line: self.end.0,
+            column: self.end.1,
+            kind: AssembleErrorKind::UnexpectedEnd,
+        })
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.last().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<Token, AssembleError> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(unexpected(&token));
+        }
+        Ok(token)
+    }
+
+    fn emit_byte(&mut self, byte: u8, token: &Token) -> Result<(), AssembleError> {
+        let offset = self.here - usize::from(OPS_START_ADDRESS);
+        if self.here > 0xFFFF {
+            let kind = AssembleErrorKind::ValueOutOfRange {
+                value: self.here as i64,
+                max: 0xFFFF,
+            };
+            return Err(error(token, kind));
+        }
+        if self.rom.len() <= offset {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit(&mut self, ins: Instruction, token: &Token) -> Result<(), AssembleError> {
+        let opcode = u16::try_from(ins).map_err(|_| {
+            error(
+                token,
+                AssembleErrorKind::InvalidOperands(token.text.clone()),
+            )
+        })?;
+        let [high, low] = opcode.to_be_bytes();
+        self.emit_byte(high, token)?;
+        self.emit_byte(low, token)
+    }
+
+    /// Emits a jump whose target is patched in later
+    fn emit_jump(&mut self, token: &Token) -> Result<usize, AssembleError> {
+        let addr = self.here;
+        self.emit(Instruction::Jump(0), token)?;
+        Ok(addr)
+    }
+
+    fn patch(
+        &mut self,
+        addr: usize,
+        kind: FixupKind,
+        value: i64,
+        token: &Token,
+    ) -> Result<(), AssembleError> {
+        let max = match kind {
+            FixupKind::Addr12 | FixupKind::Unpack(_) => 0xFFF,
+            FixupKind::Addr16 => 0xFFFF,
+        };
+        let value = check(value, 0, max, token)? as u16;
+        let offset = addr - usize::from(OPS_START_ADDRESS);
+        match kind {
+            FixupKind::Addr12 => {
+                self.rom[offset] = (self.rom[offset] & 0xF0) | (value >> 8) as u8;
+                self.rom[offset + 1] = value as u8;
+            }
+            FixupKind::Addr16 => {
+                self.rom[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+            }
+            FixupKind::Unpack(nibble) => {
+                self.rom[offset + 1] = (nibble << 4) | (value >> 8) as u8;
+                self.rom[offset + 3] = value as u8;
+            }
+        }
+        Ok(())
+    }
+
+    /// Value of a label for the instruction at `addr`. Labels that aren't
+    /// defined yet resolve to 0 and are patched once the source is compiled.
+    fn address(
+        &mut self,
+        token: &Token,
+        addr: usize,
+        kind: FixupKind,
+    ) -> Result<u16, AssembleError> {
+        if let Some(value) = self.constant(&token.text) {
+            let max = match kind {
+                FixupKind::Addr16 => 0xFFFF,
+                _ => 0xFFF,
+            };
+            return Ok(check(value, 0, max, token)? as u16);
+        }
+        if !is_identifier(&token.text) {
+            return Err(unexpected(token));
+        }
+        self.fixups.push(Fixup {
+            addr,
+            kind,
+            token: token.clone(),
+        });
+        Ok(0)
+    }
+
+    fn constant(&self, text: &str) -> Option<i64> {
+        parse_number(text)
+            .or_else(|| self.constants.get(text).copied())
+            .or_else(|| self.labels.get(text).map(|addr| i64::from(*addr)))
+    }
+
+    fn value(&self, token: &Token, min: i64, max: i64) -> Result<i64, AssembleError> {
+        match self.constant(&token.text) {
+            Some(value) => check(value, min, max, token),
+            None if is_identifier(&token.text) => Err(error(
+                token,
+                AssembleErrorKind::UndefinedSymbol(token.text.clone()),
+            )),
+            None => Err(unexpected(token)),
+        }
+    }
+
+    /// A byte operand, negative values are stored in two's complement
+    fn byte(&self, token: &Token) -> Result<u8, AssembleError> {
+        Ok(self.value(token, -128, 0xFF)? as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u8, AssembleError> {
+        let token = self.next()?;
+        Ok(self.value(&token, 0, 0xF)? as u8)
+    }
+
+    fn register(&self, text: &str) -> Option<u8> {
+        if let Some(reg) = self.aliases.get(text) {
+            return Some(*reg);
+        }
+        let digit = text.strip_prefix(['v', 'V'])?;
+        if digit.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(digit, 16).ok()
+    }
+
+    fn reg(&mut self) -> Result<u8, AssembleError> {
+        let token = self.next()?;
+        self.register(&token.text).ok_or_else(|| unexpected(&token))
+    }
+
+    fn define(&mut self, name: &Token) -> Result<(), AssembleError> {
+        if !is_identifier(&name.text) || self.register(&name.text).is_some() {
+            return Err(unexpected(name));
+        }
+        if self.labels.contains_key(&name.text) || self.constants.contains_key(&name.text) {
+            let kind = AssembleErrorKind::DuplicateSymbol(name.text.clone());
+            return Err(error(name, kind));
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), AssembleError> {
+        let token = self.next()?;
+        match token.text.as_str() {
+            ":" => {
+                let name = self.next()?;
+                self.define(&name)?;
+                self.labels.insert(name.text, self.here as u16);
+            }
+            ":const" => {
+                let name = self.next()?;
+                let value = self.next()?;
+                let value = self.value(&value, i64::MIN, i64::MAX)?;
+                self.define(&name)?;
+                self.constants.insert(name.text, value);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let reg = self.reg()?;
+                if !is_identifier(&name.text) {
+                    return Err(unexpected(&name));
+                }
+                self.aliases.insert(name.text, reg);
+            }
+            ":calc" => {
+                let name = self.next()?;
+                self.expect("{")?;
+                let value = self.calc()?;
+                self.expect("}")?;
+                self.define(&name)?;
+                self.constants.insert(name.text, value);
+            }
+            ":macro" => self.define_macro()?,
+            ":org" => {
+                let addr = self.next()?;
+                let min = i64::from(OPS_START_ADDRESS);
+                self.here = self.value(&addr, min, 0xFFFF)? as usize;
+            }
+            ":byte" => {
+                let byte = if self.peek() == Some("{") {
+                    let open = self.next()?;
+                    let value = self.calc()?;
+                    self.expect("}")?;
+                    check(value, -128, 0xFF, &open)? as u8
+                } else {
+                    let value = self.next()?;
+                    self.byte(&value)?
+                };
+                self.emit_byte(byte, &token)?;
+            }
+            ":call" => {
+                let target = self.next()?;
+                let addr = self.address(&target, self.here, FixupKind::Addr12)?;
+                self.emit(Instruction::Call(addr), &token)?;
+            }
+            ":unpack" => {
+                let nibble = self.nibble()?;
+                let target = self.next()?;
+                let addr = self.address(&target, self.here, FixupKind::Unpack(nibble))?;
+                let high = (nibble << 4) | (addr >> 8) as u8;
+                self.emit(Instruction::LoadByte(0, high), &token)?;
+                self.emit(Instruction::LoadByte(1, addr as u8), &token)?;
+            }
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            "return" | ";" => self.emit(Instruction::Ret, &token)?,
+            "clear" => self.emit(Instruction::Cls, &token)?,
+            "hires" => self.emit(Instruction::Hires, &token)?,
+            "lores" => self.emit(Instruction::Lores, &token)?,
+            "exit" => self.emit(Instruction::Exit, &token)?,
+            "scroll-left" => self.emit(Instruction::ScrollLeft, &token)?,
+            "scroll-right" => self.emit(Instruction::ScrollRight, &token)?,
+            "scroll-down" => {
+                let rows = self.nibble()?;
+                self.emit(Instruction::ScrollDown(rows), &token)?;
+            }
+            "audio" => self.emit(Instruction::AudioPattern, &token)?,
+            "plane" => {
+                let planes = self.nibble()?;
+                self.emit(Instruction::Plane(planes), &token)?;
+            }
+            "bcd" => {
+                let x = self.reg()?;
+                self.emit(Instruction::Bcd(x), &token)?;
+            }
+            "saveflags" => {
+                let x = self.reg()?;
+                self.emit(Instruction::StoreFlags(x), &token)?;
+            }
+            "loadflags" => {
+                let x = self.reg()?;
+                self.emit(Instruction::LoadFlags(x), &token)?;
+            }
+            "save" | "load" => {
+                let x = self.reg()?;
+                let save = token.text == "save";
+                let ins = if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.reg()?;
+                    if save {
+                        Instruction::SaveRange(x, y)
+                    } else {
+                        Instruction::LoadRange(x, y)
+                    }
+                } else if save {
+                    Instruction::DumpRegs(x)
+                } else {
+                    Instruction::LoadRegs(x)
+                };
+                self.emit(ins, &token)?;
+            }
+            "sprite" => {
+                let x = self.reg()?;
+                let y = self.reg()?;
+                let rows = self.nibble()?;
+                self.emit(Instruction::Draw(x, y, rows), &token)?;
+            }
+            "jump" | "jump0" | "native" => {
+                let target = self.next()?;
+                let addr = self.address(&target, self.here, FixupKind::Addr12)?;
+                let ins = match token.text.as_str() {
+                    "jump" => Instruction::Jump(addr),
+                    "jump0" => Instruction::JumpV0(addr),
+                    _ => Instruction::Sys(addr),
+                };
+                self.emit(ins, &token)?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.reg()?;
+                let ins = match token.text.as_str() {
+                    "delay" => Instruction::SetDT(x),
+                    "buzzer" => Instruction::SetST(x),
+                    _ => Instruction::Pitch(x),
+                };
+                self.emit(ins, &token)?;
+            }
+            "i" => self.index_statement(&token)?,
+            "if" => {
+                let (then, begin) = self.condition()?;
+                let keyword = self.next()?;
+                match keyword.text.as_str() {
+                    "then" => self.emit(then, &token)?,
+                    "begin" => {
+                        self.emit(begin, &token)?;
+                        let jump = self.emit_jump(&token)?;
+                        self.blocks.push(Block::If { token, jump });
+                    }
+                    _ => return Err(unexpected(&keyword)),
+                }
+            }
+            "else" => {
+                let Some(Block::If { jump, .. }) = self.blocks.last() else {
+                    return Err(unexpected(&token));
+                };
+                let jump = *jump;
+                let end = self.emit_jump(&token)?;
+                self.patch(jump, FixupKind::Addr12, self.here as i64, &token)?;
+                if let Some(Block::If { jump, .. }) = self.blocks.last_mut() {
+                    *jump = end;
+                }
+            }
+            "end" => {
+                let Some(Block::If { jump, .. }) = self.blocks.last() else {
+                    return Err(unexpected(&token));
+                };
+                let jump = *jump;
+                self.patch(jump, FixupKind::Addr12, self.here as i64, &token)?;
+                self.blocks.pop();
+            }
+            "loop" => {
+                let start = self.here;
+                self.blocks.push(Block::Loop {
+                    token,
+                    start,
+                    breaks: Vec::new(),
+                });
+            }
+            "while" => {
+                let (_, skip) = self.condition()?;
+                self.emit(skip, &token)?;
+                let jump = self.emit_jump(&token)?;
+                let innermost = self.blocks.iter_mut().rev().find_map(|block| match block {
+                    Block::Loop { breaks, .. } => Some(breaks),
+                    Block::If { .. } => None,
+                });
+                let Some(breaks) = innermost else {
+                    return Err(unexpected(&token));
+                };
+                breaks.push(jump);
+            }
+            "again" => {
+                let Some(Block::Loop { start, .. }) = self.blocks.last() else {
+                    return Err(unexpected(&token));
+                };
+                let start = *start as u16;
+                self.emit(Instruction::Jump(start), &token)?;
+                if let Some(Block::Loop { breaks, .. }) = self.blocks.pop() {
+                    for jump in breaks {
+                        self.patch(jump, FixupKind::Addr12, self.here as i64, &token)?;
+                    }
+                }
+            }
+            ":stringmode" | ":next" | ":assert" | ":include" | "scroll-up" => {
+                let kind = AssembleErrorKind::Unsupported(token.text.clone());
+                return Err(error(&token, kind));
+            }
+            text if self.macros.contains_key(text) => self.expand(&token)?,
+            text if self.register(text).is_some() => self.register_statement(&token)?,
+            text if parse_number(text).is_some() => {
+                let byte = self.byte(&token)?;
+                self.emit_byte(byte, &token)?;
+            }
+            text if is_identifier(text) => {
+                let addr = self.address(&token, self.here, FixupKind::Addr12)?;
+                self.emit(Instruction::Call(addr), &token)?;
+            }
+            _ => return Err(unexpected(&token)),
+        }
+        Ok(())
+    }
+
+    fn index_statement(&mut self, token: &Token) -> Result<(), AssembleError> {
+        let op = self.next()?;
+        match op.text.as_str() {
+            ":=" => {
+                let source = self.next()?;
+                match source.text.as_str() {
+                    "hex" => {
+                        let x = self.reg()?;
+                        self.emit(Instruction::LoadSprite(x), token)
+                    }
+                    "bighex" => {
+                        let x = self.reg()?;
+                        self.emit(Instruction::LoadBigSprite(x), token)
+                    }
+                    "long" => {
+                        let target = self.next()?;
+                        let addr = self.address(&target, self.here + 2, FixupKind::Addr16)?;
+                        self.emit(Instruction::LoadILong, token)?;
+                        let [high, low] = addr.to_be_bytes();
+                        self.emit_byte(high, token)?;
+                        self.emit_byte(low, token)
+                    }
+                    _ => {
+                        let addr = self.address(&source, self.here, FixupKind::Addr12)?;
+                        self.emit(Instruction::LoadI(addr), token)
+                    }
+                }
+            }
+            "+=" => {
+                let x = self.reg()?;
+                self.emit(Instruction::AddI(x), token)
+            }
+            _ => Err(unexpected(&op)),
+        }
+    }
+
+    fn register_statement(&mut self, token: &Token) -> Result<(), AssembleError> {
+        let x = self.register(&token.text).unwrap_or_default();
+        let op = self.next()?;
+        let source = self.next()?;
+        let y = self.register(&source.text);
+        let ins = match (op.text.as_str(), y) {
+            (":=", Some(y)) => Instruction::LoadReg(x, y),
+            (":=", None) => match source.text.as_str() {
+                "random" => {
+                    let mask = self.next()?;
+                    Instruction::Rand(x, self.byte(&mask)?)
+                }
+                "key" => Instruction::WaitKey(x),
+                "delay" => Instruction::LoadDT(x),
+                _ => Instruction::LoadByte(x, self.byte(&source)?),
+            },
+            ("+=", Some(y)) => Instruction::AddReg(x, y),
+            ("+=", None) => Instruction::AddByte(x, self.byte(&source)?),
+            ("-=", Some(y)) => Instruction::SubReg(x, y),
+            ("-=", None) => Instruction::AddByte(x, self.byte(&source)?.wrapping_neg()),
+            ("=-", Some(y)) => Instruction::SubnReg(x, y),
+            ("|=", Some(y)) => Instruction::OrReg(x, y),
+            ("&=", Some(y)) => Instruction::AndReg(x, y),
+            ("^=", Some(y)) => Instruction::XorReg(x, y),
+            (">>=", Some(y)) => Instruction::ShrReg(x, y),
+            ("<<=", Some(y)) => Instruction::ShlReg(x, y),
+            ("=-" | "|=" | "&=" | "^=" | ">>=" | "<<=", None) => {
+                return Err(unexpected(&source));
+            }
+            _ => return Err(unexpected(&op)),
+        };
+        self.emit(ins, token)
+    }
+
+    /// Parses the condition of `if` or `while`. Returns the skip that runs
+    /// the next instruction only when the condition holds, and the skip that
+    /// runs it only when the condition fails.
+    fn condition(&mut self) -> Result<(Instruction, Instruction), AssembleError> {
+        let x = self.reg()?;
+        let op = self.next()?;
+        let skips = match op.text.as_str() {
+            "key" => (Instruction::SkipIfNotKey(x), Instruction::SkipIfKey(x)),
+            "-key" => (Instruction::SkipIfKey(x), Instruction::SkipIfNotKey(x)),
+            "==" | "!=" => {
+                let source = self.next()?;
+                let (eq, ne) = match self.register(&source.text) {
+                    Some(y) => (Instruction::SkipEqReg(x, y), Instruction::SkipNeReg(x, y)),
+                    None => {
+                        let byte = self.byte(&source)?;
+                        (
+                            Instruction::SkipEqByte(x, byte),
+                            Instruction::SkipNeByte(x, byte),
+                        )
+                    }
+                };
+                if op.text == "==" { (ne, eq) } else { (eq, ne) }
+            }
+            "<" | ">" | "<=" | ">=" => {
+                // As Octo does, subtract into vf and test the borrow flag
+                if x == 0xF {
+                    let kind = AssembleErrorKind::InvalidOperands(op.text.clone());
+                    return Err(error(&op, kind));
+                }
+                let source = self.next()?;
+                let load = match self.register(&source.text) {
+                    Some(y) => Instruction::LoadReg(0xF, y),
+                    None => Instruction::LoadByte(0xF, self.byte(&source)?),
+                };
+                self.emit(load, &op)?;
+                // The flag is set when the subtraction doesn't borrow:
+                // vx >= y for vf =- vx, y >= vx for vf -= vx
+                let (sub, flag) = match op.text.as_str() {
+                    ">=" => (Instruction::SubnReg(0xF, x), 1),
+                    "<" => (Instruction::SubnReg(0xF, x), 0),
+                    "<=" => (Instruction::SubReg(0xF, x), 1),
+                    _ => (Instruction::SubReg(0xF, x), 0),
+                };
+                self.emit(sub, &op)?;
+                (
+                    Instruction::SkipNeByte(0xF, flag),
+                    Instruction::SkipEqByte(0xF, flag),
+                )
+            }
+            _ => return Err(unexpected(&op)),
+        };
+        Ok(skips)
+    }
+
+    fn define_macro(&mut self) -> Result<(), AssembleError> {
+        let name = self.next()?;
+        if !is_identifier(&name.text) {
+            return Err(unexpected(&name));
+        }
+        let mut params = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "{" {
+                break;
+            }
+            params.push(token.text);
+        }
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => break,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            body.push(token);
+        }
+        self.macros.insert(name.text, Macro { params, body });
+        Ok(())
+    }
+
+    fn expand(&mut self, token: &Token) -> Result<(), AssembleError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            let kind = AssembleErrorKind::RecursiveSymbol(token.text.clone());
+            return Err(error(token, kind));
+        }
+        let count = self.macros[&token.text].params.len();
+        let args = (0..count)
+            .map(|_| self.next())
+            .collect::<Result<Vec<_>, _>>()?;
+        let Macro { params, body } = &self.macros[&token.text];
+        let expanded: Vec<Token> = body
+            .iter()
+            .rev()
+            .map(
+                |token| match params.iter().position(|param| *param == token.text) {
+                    Some(index) => args[index].clone(),
+                    None => token.clone(),
+                },
+            )
+            .collect();
+        self.tokens.extend(expanded);
+        Ok(())
+    }
+
+    /// Evaluates a `:calc` expression up to the closing brace
+    fn calc(&mut self) -> Result<i64, AssembleError> {
+        let lhs = self.calc_term()?;
+        if matches!(self.peek(), Some("}" | ")") | None) {
+            return Ok(lhs);
+        }
+        let op = self.next()?;
+        let rhs = self.calc()?;
+        let value = match op.text.as_str() {
+            "+" => lhs.checked_add(rhs).ok_or_else(|| overflow(&op, rhs > 0))?,
+            "-" => lhs.checked_sub(rhs).ok_or_else(|| overflow(&op, rhs < 0))?,
+            "*" => lhs
+                .checked_mul(rhs)
+                .ok_or_else(|| overflow(&op, (lhs < 0) == (rhs < 0)))?,
+            "/" | "%" if rhs == 0 => {
+                let kind = AssembleErrorKind::InvalidExpression("division by zero".to_string());
+                return Err(error(&op, kind));
+            }
+            "/" => lhs.checked_div(rhs).ok_or_else(|| overflow(&op, true))?,
+            // Only i64::MIN % -1 overflows, and its remainder is 0
+            "%" => lhs.checked_rem(rhs).unwrap_or(0),
+            "&" => lhs & rhs,
+            "|" => lhs | rhs,
+            "^" => lhs ^ rhs,
+            "<<" | ">>" if rhs < 0 => {
+                let kind = AssembleErrorKind::InvalidExpression("negative shift".to_string());
+                return Err(error(&op, kind));
+            }
+            "<<" => u32::try_from(rhs)
+                .ok()
+                .and_then(|shift| lhs.checked_shl(shift))
+                .filter(|&value| value >> rhs == lhs)
+                .ok_or_else(|| overflow(&op, lhs > 0))?,
+            ">>" => lhs >> rhs.min(63),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            "<" => i64::from(lhs < rhs),
+            ">" => i64::from(lhs > rhs),
+            "<=" => i64::from(lhs <= rhs),
+            ">=" => i64::from(lhs >= rhs),
+            "==" => i64::from(lhs == rhs),
+            "!=" => i64::from(lhs != rhs),
+            _ => return Err(unexpected(&op)),
+        };
+        Ok(value)
+    }
+
+    fn calc_term(&mut self) -> Result<i64, AssembleError> {
+        let token = self.next()?;
+        match token.text.as_str() {
+            "(" => {
+                let value = self.calc()?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            "-" => {
+                let value = self.calc_term()?;
+                value.checked_neg().ok_or_else(|| overflow(&token, true))
+            }
+            "~" => Ok(!self.calc_term()?),
+            "!" => Ok(i64::from(self.calc_term()? == 0)),
+            "HERE" => Ok(self.here as i64),
+            _ => self.value(&token, i64::MIN, i64::MAX),
+        }
+    }
+}
+
+/// A `:calc` result past the range of i64, on the positive side or not
+fn overflow(token: &Token, positive: bool) -> AssembleError {
+    let value = if positive { i64::MAX } else { i64::MIN };
+    let kind = AssembleErrorKind::ValueOutOfRange {
+        value,
+        max: u16::MAX,
+    };
+    error(token, kind)
+}
+
+fn check(value: i64, min: i64, max: i64, token: &Token) -> Result<i64, AssembleError> {
+    if (min..=max).contains(&value) {
+        Ok(value)
+    } else {
+        let kind = AssembleErrorKind::ValueOutOfRange {
+            value,
+            max: max.clamp(0, 0xFFFF) as u16,
+        };
+        Err(error(token, kind))
+    }
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or_default();
+        let mut current: Option<(usize, String)> = None;
+        for (column, ch) in code.chars().chain([' ']).enumerate() {
+            if !ch.is_whitespace() {
+                current
+                    .get_or_insert_with(|| (column, String::new()))
+                    .1
+                    .push(ch);
+            } else if let Some((first, text)) = current.take() {
+                tokens.push(Token {
+                    text,
+                    line: index + 1,
+                    column: first + 1,
+                });
+            }
+        }
+    }
+    tokens
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|ch| ch.is_ascii_alphabetic() || ch == '_')
+        && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_' || ch == '-')
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()?
+    } else if digits.starts_with(|ch: char| ch.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Chip8;
+
+    fn compile(source: &str) -> Vec<u8> {
+        compile_octo(source).unwrap().rom
+    }
+
+    fn error_at(source: &str) -> (usize, usize, AssembleErrorKind) {
+        let err = compile_octo(source).unwrap_err();
+        (err.line, err.column, err.kind)
+    }
+
+    #[test]
+    fn test_statements() {
+        let program = compile_octo(
+            "\
+: main
+  v0 := 5  v1 += 2  v2 -= 1  v3 =- v4
+  i := digit  sprite v0 v1 5
+  delay := v0  v5 := random 0x0F
+  save v3  load v1 - v2
+  draw  exit
+: draw  return
+: digit 0xF0 0x90 0xF0
+",
+        )
+        .unwrap();
+        assert_eq!(
+            program.rom,
+            vec![
+                0x60, 0x05, 0x71, 0x02, 0x72, 0xFF, 0x83, 0x47, 0xA2, 0x1A, 0xD0, 0x15, 0xF0, 0x15,
+                0xC5, 0x0F, 0xF3, 0x55, 0x51, 0x23, 0x22, 0x18, 0x00, 0xFD, 0x00, 0xEE, 0xF0, 0x90,
+                0xF0,
+            ]
+        );
+        assert_eq!(program.labels["draw"], 0x218);
+        assert_eq!(program.label_at(0x21A), Some("digit"));
+    }
+
+    #[test]
+    fn test_control_flow() {
+        // 0x200: if v0 == 1 then v1 := 2
+        // 0x204: if v0 key begin v1 := 3 else v1 := 4 end
+        // 0x20E: loop v2 += 1 while v2 != 9 again
+        assert_eq!(
+            compile(
+                ": main
+                 if v0 == 1 then v1 := 2
+                 if v0 key begin v1 := 3 else v1 := 4 end
+                 loop v2 += 1 while v2 != 9 again"
+            ),
+            vec![
+                0x40, 0x01, 0x61, 0x02, 0xE0, 0x9E, 0x12, 0x0C, 0x61, 0x03, 0x12, 0x0E, 0x61, 0x04,
+                0x72, 0x01, 0x42, 0x09, 0x12, 0x16, 0x12, 0x0E,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_metaprogramming() {
+        assert_eq!(
+            compile(
+                ":const WIDTH 64
+                 :calc CENTER { ( WIDTH / 2 ) - 4 }
+                 :alias x v3
+                 :macro center reg { reg := CENTER }
+                 : main
+                 center x
+                 :byte { 1 + 2 * 3 }
+                 :byte { 10 - 4 - 3 }"
+            ),
+            vec![0x12, 0x02, 0x63, 0x1C, 0x07, 0x09]
+        );
+    }
+
+    #[test]
+    fn test_jump_to_main() {
+        assert_eq!(
+            compile(
+                ": data 0xAA
+                 : main
+                 i := long data
+                 :unpack 0xA data
+                 jump main"
+            ),
+            vec![
+                0x12, 0x03, 0xAA, 0xF0, 0x00, 0x02, 0x02, 0x60, 0xA2, 0x61, 0x02, 0x12, 0x03
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            error_at(": main\n  jump nowhere"),
+            (
+                2,
+                8,
+                AssembleErrorKind::UndefinedSymbol("nowhere".to_string())
+            )
+        );
+        assert_eq!(
+            error_at(": main\n  loop v0 += 1"),
+            (2, 3, AssembleErrorKind::Unclosed("loop".to_string()))
+        );
+        assert_eq!(
+            error_at(": main v0 := 256"),
+            (
+                1,
+                14,
+                AssembleErrorKind::ValueOutOfRange {
+                    value: 256,
+                    max: 0xFF
+                }
+            )
+        );
+        assert_eq!(
+            error_at(": main v0 :="),
+            (1, 11, AssembleErrorKind::UnexpectedEnd)
+        );
+        assert_eq!(
+            error_at(": main end"),
+            (1, 8, AssembleErrorKind::UnexpectedToken("end".to_string()))
+        );
+        assert_eq!(
+            error_at("v0 := 1"),
+            (1, 1, AssembleErrorKind::UndefinedSymbol("main".to_string()))
+        );
+        assert_eq!(
+            error_at(":calc BIG { 0x7FFFFFFFFFFFFFFF * 2 }\n: main"),
+            (
+                1,
+                32,
+                AssembleErrorKind::ValueOutOfRange {
+                    value: i64::MAX,
+                    max: 0xFFFF
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn test_run_compiled_program() {
+        // Sums 1 to 10 into v1
+        let rom = compile(
+            ": main
+             v0 := 0  v1 := 0
+             loop
+               v0 += 1
+               v1 += v0
+               while v0 != 10
+             again
+             : halt jump halt",
+        );
+        let mut chip8 = Chip8::default();
+        chip8.load_rom(&rom).unwrap();
+        chip8.run_frame(100).unwrap();
+        assert_eq!(chip8.v[0x0], 10);
+        assert_eq!(chip8.v[0x1], 55);
+    }
+
+    #[test]
+    fn test_run_comparisons() {
+        for op in ["<", ">", "<=", ">="] {
+            for (a, b) in [(3, 7), (7, 3), (5, 5), (0, 255)] {
+                // v2 counts the then and begin forms with a register and a
+                // byte, v3 the else branches
+                let rom = compile(&format!(
+                    ": main
+                     v0 := {a}  v1 := {b}
+                     if v0 {op} v1 then v2 += 1
+                     if v0 {op} {b} then v2 += 1
+                     if v0 {op} v1 begin v2 += 1 else v3 += 1 end
+                     if v0 {op} {b} begin v2 += 1 else v3 += 1 end
+                     : halt jump halt"
+                ));
+                let mut chip8 = Chip8::default();
+                chip8.load_rom(&rom).unwrap();
+                chip8.run_frame(100).unwrap();
+                let holds = match op {
+                    "<" => a < b,
+                    ">" => a > b,
+                    "<=" => a <= b,
+                    _ => a >= b,
+                };
+                let expected = if holds { (4, 0) } else { (0, 2) };
+                assert_eq!((chip8.v[0x2], chip8.v[0x3]), expected, "{a} {op} {b}");
+                assert_eq!((chip8.v[0x0], chip8.v[0x1]), (a, b));
+            }
+        }
+        let rom = compile(
+            ": main
+             v0 := 0
+             loop
+               v0 += 1
+               while v0 < 10
+             again
+             : halt jump halt",
+        );
+        let mut chip8 = Chip8::default();
+        chip8.load_rom(&rom).unwrap();
+        chip8.run_frame(100).unwrap();
+        assert_eq!(chip8.v[0x0], 10);
+        assert_eq!(
+            error_at(": main if vf < 3 then v0 := 1"),
+            (1, 14, AssembleErrorKind::InvalidOperands("<".to_string()))
+        );
+    }
+}
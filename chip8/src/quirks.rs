@@ -0,0 +1,95 @@
+/// What 0xFX55/0xFX65 do to I after storing or loading V0 through VX
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LoadStoreIncrement {
+    /// I is left alone, as in SUPER-CHIP 1.1
+    Unchanged,
+    /// I is incremented by X, as in CHIP-48 and SUPER-CHIP 1.0
+    ByX,
+    /// I is left pointing past the last register, as on the COSMAC VIP
+    #[default]
+    ByXPlusOne,
+}
+
+/// Behaviour of the CHIP-8 instructions whose semantics differ between
+/// interpreters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Quirks {
+    /// 0x8XY6/0x8XYE shift VY and store the result in VX, instead of
+    /// shifting VX in place
+    pub shift_uses_vy: bool,
+    /// How 0xFX55/0xFX65 change I
+    pub load_store_increment: LoadStoreIncrement,
+    /// 0x8XY1/0x8XY2/0x8XY3 reset VF to 0
+    pub vf_reset: bool,
+    /// 0xDXYN clips sprites at the screen edge instead of wrapping them
+    pub clipping: bool,
+    /// 0xBNNN behaves as 0xBXNN and jumps to XNN plus VX instead of V0
+    pub jump_uses_vx: bool,
+    /// 0xDXYN waits for the next 60 Hz frame before execution continues
+    pub display_wait: bool,
+    /// 0xFX0A completes when a key is released instead of when it is
+    /// pressed, and the timers stop counting down while it waits
+    pub key_wait_release: bool,
+}
+
+impl Quirks {
+    /// The original COSMAC VIP interpreter
+    pub const fn cosmac_vip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increment: LoadStoreIncrement::ByXPlusOne,
+            vf_reset: true,
+            clipping: true,
+            jump_uses_vx: false,
+            display_wait: true,
+            key_wait_release: true,
+        }
+    }
+
+    /// CHIP-48 on the HP-48 calculators
+    pub const fn chip48() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increment: LoadStoreIncrement::ByX,
+            vf_reset: false,
+            clipping: true,
+            jump_uses_vx: true,
+            display_wait: false,
+            key_wait_release: false,
+        }
+    }
+
+    /// SUPER-CHIP 1.1
+    pub const fn superchip() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increment: LoadStoreIncrement::Unchanged,
+            vf_reset: false,
+            clipping: true,
+            jump_uses_vx: true,
+            display_wait: false,
+            key_wait_release: false,
+        }
+    }
+
+    /// XO-CHIP as implemented by Octo
+    pub const fn xochip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increment: LoadStoreIncrement::ByXPlusOne,
+            vf_reset: false,
+            clipping: false,
+            jump_uses_vx: false,
+            display_wait: false,
+            key_wait_release: false,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::cosmac_vip()
+    }
+}
@@ -0,0 +1,502 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use crate::{Chip8, HIRES_HEIGHT, HIRES_WIDTH, Palette, Screenshot};
+
+/// Merges runs of identical frames and times the remaining ones
+#[derive(Debug)]
+struct Timeline {
+    fps: u32,
+    /// Frames seen so far
+    frame: u64,
+    /// The latest distinct frame and the frame it was first seen on
+    pending: Option<(Screenshot, u64)>,
+}
+
+impl Timeline {
+    fn new(fps: u32) -> Self {
+        Timeline {
+            fps: fps.max(1),
+            frame: 0,
+            pending: None,
+        }
+    }
+
+    /// Adds the next frame. Returns the previous distinct frame with the
+    /// frames it started and ended on once it has been replaced.
+    fn push(&mut self, screenshot: Screenshot) -> Option<(Screenshot, u64, u64)> {
+        let frame = self.frame;
+        self.frame += 1;
+        match &self.pending {
+            Some((pending, _)) if *pending == screenshot => None,
+            _ => self
+                .pending
+                .replace((screenshot, frame))
+                .map(|(pending, start)| (pending, start, frame)),
+        }
+    }
+
+    fn finish(&mut self) -> Option<(Screenshot, u64, u64)> {
+        let end = self.frame;
+        self.pending
+            .take()
+            .map(|(pending, start)| (pending, start, end))
+    }
+
+    /// Time of `frame` in hundredths of a second
+    fn centiseconds(&self, frame: u64) -> u64 {
+        (frame * 100 + u64::from(self.fps) / 2) / u64::from(self.fps)
+    }
+}
+
+/// Every frame is recorded at the SUPER-CHIP resolution, with low
+/// resolution pixels doubled, so the size is the same throughout
+fn to_hires(screenshot: &Screenshot) -> Screenshot {
+    if screenshot.width == HIRES_WIDTH {
+        return screenshot.clone();
+    }
+    let pixels = screenshot
+        .pixels
+        .chunks(usize::from(screenshot.width))
+        .flat_map(|row| {
+            let doubled: Vec<u8> = row.iter().flat_map(|&pixel| [pixel, pixel]).collect();
+            [doubled.clone(), doubled]
+        })
+        .flatten()
+        .collect();
+    Screenshot {
+        width: HIRES_WIDTH,
+        height: HIRES_HEIGHT,
+        pixels,
+    }
+}
+
+/// Records an animated GIF, one image per distinct frame. Frames are timed
+/// at `fps`, except that images shown for less than 20 ms are dropped, as
+/// viewers slow those down.
+#[derive(Debug)]
+pub struct GifRecorder<W: Write> {
+    out: W,
+    scale: u32,
+    timeline: Timeline,
+    /// The last image written, to only encode the part that changed
+    previous: Option<Screenshot>,
+    /// Frame on which the image waiting to be written started
+    start: Option<u64>,
+}
+
+impl<W: Write> GifRecorder<W> {
+    /// Writes the GIF header with room for `scale` times the SUPER-CHIP
+    /// resolution
+    pub fn new(mut out: W, fps: u32, scale: u32, palette: &Palette) -> io::Result<Self> {
+        let scale = scale.max(1);
+        let width = u16::try_from(u32::from(HIRES_WIDTH) * scale);
+        let height = u16::try_from(u32::from(HIRES_HEIGHT) * scale);
+        let (Ok(width), Ok(height)) = (width, height) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "scale too large for GIF",
+            ));
+        };
+        out.write_all(b"GIF89a")?;
+        out.write_all(&width.to_le_bytes())?;
+        out.write_all(&height.to_le_bytes())?;
+        // A global colour table of 4 entries, background colour 0
+        out.write_all(&[0xF1, 0, 0])?;
+        out.write_all(palette.0.as_flattened())?;
+        // Loop forever
+        out.write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+        Ok(GifRecorder {
+            out,
+            scale,
+            timeline: Timeline::new(fps),
+            previous: None,
+            start: None,
+        })
+    }
+
+    pub fn capture(&mut self, chip8: &Chip8) -> io::Result<()> {
+        self.push(Screenshot::capture(chip8))
+    }
+
+    /// Adds the next frame
+    pub fn push(&mut self, screenshot: Screenshot) -> io::Result<()> {
+        match self.timeline.push(to_hires(&screenshot)) {
+            Some((image, start, end)) => self.write_image(image, start, end),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the last frame and the trailer
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some((image, start, end)) = self.timeline.finish() {
+            let start = self.start.take().unwrap_or(start);
+            self.encode(&image, start, end.max(start + 1))?;
+        }
+        self.out.write_all(b"\x3B")?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn write_image(&mut self, image: Screenshot, start: u64, end: u64) -> io::Result<()> {
+        let start = self.start.take().unwrap_or(start);
+        let delay = self.timeline.centiseconds(end) - self.timeline.centiseconds(start);
+        if delay < 2 {
+            // Too short to show, the next image takes over its time
+            self.start = Some(start);
+            return Ok(());
+        }
+        self.encode(&image, start, end)
+    }
+
+    fn encode(&mut self, image: &Screenshot, start: u64, end: u64) -> io::Result<()> {
+        let delay = self.timeline.centiseconds(end) - self.timeline.centiseconds(start);
+        let delay = u16::try_from(delay).unwrap_or(u16::MAX);
+        let width = usize::from(image.width);
+        // Only the rectangle that differs from the last image is stored
+        let (left, top, right, bottom) = match &self.previous {
+            Some(previous) => changed_area(previous, image).unwrap_or((0, 0, 1, 1)),
+            None => (0, 0, width, usize::from(image.height)),
+        };
+        let scale = self.scale as usize;
+        let mut pixels = Vec::with_capacity((right - left) * (bottom - top) * scale * scale);
+        for y in top..bottom {
+            let row = &image.pixels[y * width + left..y * width + right];
+            let scaled: Vec<u8> = row
+                .iter()
+                .flat_map(|&pixel| std::iter::repeat_n(pixel & 0x3, scale))
+                .collect();
+            for _ in 0..scale {
+                pixels.extend_from_slice(&scaled);
+            }
+        }
+
+        // Graphic control extension with the delay, leaving the image in
+        // place for the next one to be drawn over
+        self.out.write_all(&[0x21, 0xF9, 0x04, 0x04])?;
+        self.out.write_all(&delay.to_le_bytes())?;
+        self.out.write_all(&[0, 0])?;
+        self.out.write_all(&[0x2C])?;
+        for value in [left, top, right - left, bottom - top] {
+            self.out
+                .write_all(&((value * scale) as u16).to_le_bytes())?;
+        }
+        self.out.write_all(&[0, GIF_MIN_CODE_SIZE])?;
+        for block in lzw(&pixels).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0])?;
+        self.previous = Some(image.clone());
+        Ok(())
+    }
+}
+
+/// Bounding box of the differing pixels as left, top, right and bottom,
+/// with right and bottom exclusive
+fn changed_area(a: &Screenshot, b: &Screenshot) -> Option<(usize, usize, usize, usize)> {
+    let width = usize::from(a.width);
+    let mut area: Option<(usize, usize, usize, usize)> = None;
+    for (index, _) in a
+        .pixels
+        .iter()
+        .zip(&b.pixels)
+        .enumerate()
+        .filter(|(_, (a, b))| a != b)
+    {
+        let (x, y) = (index % width, index / width);
+        area = Some(match area {
+            None => (x, y, x + 1, y + 1),
+            Some((left, top, right, bottom)) => {
+                (left.min(x), top.min(y), right.max(x + 1), bottom.max(y + 1))
+            }
+        });
+    }
+    area
+}
+
+/// Codes start one bit wider than the 2 bit colour indices
+const GIF_MIN_CODE_SIZE: u8 = 2;
+
+/// Compresses colour indices with GIF's variant of LZW
+fn lzw(pixels: &[u8]) -> Vec<u8> {
+    let clear = 1u16 << GIF_MIN_CODE_SIZE;
+    let end = clear + 1;
+    let mut out = Vec::new();
+    let (mut bits, mut count) = (0u32, 0u32);
+    let mut write = |code: u16, size: u32| {
+        bits |= u32::from(code) << count;
+        count += size;
+        while count >= 8 {
+            out.push(bits as u8);
+            bits >>= 8;
+            count -= 8;
+        }
+    };
+
+    let mut codes: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut size = u32::from(GIF_MIN_CODE_SIZE) + 1;
+    write(clear, size);
+    let mut prefix: Option<u16> = None;
+    for &pixel in pixels {
+        let Some(current) = prefix else {
+            prefix = Some(u16::from(pixel));
+            continue;
+        };
+        if let Some(&code) = codes.get(&(current, pixel)) {
+            prefix = Some(code);
+            continue;
+        }
+        write(current, size);
+        if next < 4096 {
+            codes.insert((current, pixel), next);
+            next += 1;
+            if next > 1 << size && size < 12 {
+                size += 1;
+            }
+        } else {
+            write(clear, size);
+            codes.clear();
+            next = end + 1;
+            size = u32::from(GIF_MIN_CODE_SIZE) + 1;
+        }
+        prefix = Some(u16::from(pixel));
+    }
+    if let Some(current) = prefix {
+        write(current, size);
+    }
+    write(end, size);
+    if count > 0 {
+        out.push(bits as u8);
+    }
+    out
+}
+
+/// Records numbered PNG images, one per distinct frame, into a directory
+/// along with `frames.ffconcat`, which lists how long each is shown. Encode
+/// them with `ffmpeg -f concat -i frames.ffconcat out.mp4`.
+#[derive(Debug)]
+pub struct PngSequenceRecorder {
+    dir: PathBuf,
+    scale: u32,
+    palette: Palette,
+    timeline: Timeline,
+    index: usize,
+    list: io::BufWriter<fs::File>,
+}
+
+impl PngSequenceRecorder {
+    /// Creates `dir` if needed
+    pub fn new(
+        dir: impl Into<PathBuf>,
+        fps: u32,
+        scale: u32,
+        palette: &Palette,
+    ) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let mut list = io::BufWriter::new(fs::File::create(dir.join("frames.ffconcat"))?);
+        writeln!(list, "ffconcat version 1.0")?;
+        Ok(PngSequenceRecorder {
+            dir,
+            scale,
+            palette: *palette,
+            timeline: Timeline::new(fps),
+            index: 0,
+            list,
+        })
+    }
+
+    pub fn capture(&mut self, chip8: &Chip8) -> io::Result<()> {
+        self.push(Screenshot::capture(chip8))
+    }
+
+    /// Adds the next frame
+    pub fn push(&mut self, screenshot: Screenshot) -> io::Result<()> {
+        match self.timeline.push(to_hires(&screenshot)) {
+            Some((image, start, end)) => self.write_image(&image, start, end),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the last frame and completes the list. Returns the number of
+    /// images written.
+    pub fn finish(mut self) -> io::Result<usize> {
+        if let Some((image, start, end)) = self.timeline.finish() {
+            self.write_image(&image, start, end)?;
+            // ffmpeg ignores the duration of the last entry unless the file
+            // is repeated
+            writeln!(self.list, "file '{}'", frame_name(self.index - 1))?;
+        }
+        self.list.flush()?;
+        Ok(self.index)
+    }
+
+    fn write_image(&mut self, image: &Screenshot, start: u64, end: u64) -> io::Result<()> {
+        let name = frame_name(self.index);
+        let out = io::BufWriter::new(fs::File::create(self.dir.join(&name))?);
+        image.write_png(out, self.scale, &self.palette)?;
+        let duration = (end - start) as f64 / f64::from(self.timeline.fps);
+        writeln!(self.list, "file '{}'\nduration {:.6}", name, duration)?;
+        self.index += 1;
+        Ok(())
+    }
+}
+
+fn frame_name(index: usize) -> String {
+    format!("frame-{:05}.png", index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen(lit: &[usize]) -> Screenshot {
+        let mut pixels = vec![0; 64 * 32];
+        for &index in lit {
+            pixels[index] = 1;
+        }
+        Screenshot {
+            width: 64,
+            height: 32,
+            pixels,
+        }
+    }
+
+    /// Reads back the colour indices of an LZW stream
+    fn unlzw(data: &[u8]) -> Vec<u8> {
+        let clear = 1usize << GIF_MIN_CODE_SIZE;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let mut size = usize::from(GIF_MIN_CODE_SIZE) + 1;
+        let mut pos = 0;
+        let mut previous: Option<Vec<u8>> = None;
+        let mut out = Vec::new();
+        loop {
+            let mut code = 0;
+            for bit in 0..size {
+                let set = data[(pos + bit) / 8] >> ((pos + bit) % 8) & 1;
+                code |= usize::from(set) << bit;
+            }
+            pos += size;
+            if code == clear {
+                table = (0..clear).map(|n| vec![n as u8]).collect();
+                table.extend([Vec::new(), Vec::new()]);
+                size = usize::from(GIF_MIN_CODE_SIZE) + 1;
+                previous = None;
+                continue;
+            }
+            if code == clear + 1 {
+                return out;
+            }
+            let entry = match (table.get(code), &previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(previous)) => [previous.as_slice(), &previous[..1]].concat(),
+                (None, None) => panic!("invalid code {}", code),
+            };
+            out.extend_from_slice(&entry);
+            if let Some(previous) = previous
+                && table.len() < 4096
+            {
+                table.push([previous.as_slice(), &entry[..1]].concat());
+            }
+            if table.len() == 1 << size && size < 12 {
+                size += 1;
+            }
+            previous = Some(entry);
+        }
+    }
+
+    #[test]
+    fn test_lzw_round_trip() {
+        let mut state = 1u32;
+        let noise: Vec<u8> = (0..20000)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 16) as u8 & 0x3
+            })
+            .collect();
+        for pixels in [vec![0; 10000], noise, vec![1, 2, 3], vec![2]] {
+            assert_eq!(unlzw(&lzw(&pixels)), pixels);
+        }
+    }
+
+    #[test]
+    fn test_timeline_dedupes() {
+        let mut timeline = Timeline::new(60);
+        assert_eq!(timeline.push(screen(&[])), None);
+        assert_eq!(timeline.push(screen(&[])), None);
+        assert_eq!(timeline.push(screen(&[5])), Some((screen(&[]), 0, 2)));
+        assert_eq!(timeline.finish(), Some((screen(&[5]), 2, 3)));
+        assert_eq!(timeline.centiseconds(60), 100);
+        assert_eq!(timeline.centiseconds(1), 2);
+    }
+
+    #[test]
+    fn test_gif() {
+        let mut gif = GifRecorder::new(Vec::new(), 60, 2, &Palette::default()).unwrap();
+        for frame in 0..30 {
+            gif.push(screen(&[frame / 10])).unwrap();
+        }
+        let gif = gif.finish().unwrap();
+        assert_eq!(&gif[..6], b"GIF89a");
+        assert_eq!(&gif[6..10], &[0, 1, 128, 0]);
+        assert_eq!(gif.last(), Some(&0x3B));
+        // One image per distinct frame, each shown for 10 frames, rounded to
+        // hundredths of a second
+        let images: Vec<usize> = (0..gif.len() - 8)
+            .filter(|&i| gif[i..i + 4] == [0x21, 0xF9, 0x04, 0x04])
+            .collect();
+        assert_eq!(images.len(), 3);
+        let delays: Vec<u16> = images
+            .iter()
+            .map(|&i| u16::from_le_bytes([gif[i + 4], gif[i + 5]]))
+            .collect();
+        assert_eq!(delays, [17, 16, 17]);
+        // Later images only cover the two changed pixels, doubled twice
+        let second = images[1] + 8;
+        assert_eq!(gif[second], 0x2C);
+        assert_eq!(&gif[second + 1..second + 9], &[0, 0, 0, 0, 8, 0, 4, 0]);
+    }
+
+    #[test]
+    fn test_gif_drops_short_images() {
+        let mut gif = GifRecorder::new(Vec::new(), 60, 1, &Palette::default()).unwrap();
+        for frame in 0..6 {
+            gif.push(screen(&[frame])).unwrap();
+        }
+        let gif = gif.finish().unwrap();
+        let delays: Vec<u16> = (0..gif.len() - 8)
+            .filter(|&i| gif[i..i + 4] == [0x21, 0xF9, 0x04, 0x04])
+            .map(|i| u16::from_le_bytes([gif[i + 4], gif[i + 5]]))
+            .collect();
+        assert_eq!(delays.iter().sum::<u16>(), 10);
+        assert!(delays.iter().all(|&delay| delay >= 2));
+    }
+
+    #[test]
+    fn test_png_sequence() {
+        let dir = std::env::temp_dir().join(format!("chip8-png-sequence-{}", std::process::id()));
+        let mut recorder = PngSequenceRecorder::new(&dir, 60, 1, &Palette::default()).unwrap();
+        for frame in 0..30 {
+            recorder.push(screen(&[frame / 20])).unwrap();
+        }
+        assert_eq!(recorder.finish().unwrap(), 2);
+        let list = fs::read_to_string(dir.join("frames.ffconcat")).unwrap();
+        assert_eq!(
+            list,
+            "ffconcat version 1.0\n\
+             file 'frame-00000.png'\nduration 0.333333\n\
+             file 'frame-00001.png'\nduration 0.166667\n\
+             file 'frame-00001.png'\n"
+        );
+        assert!(
+            fs::read(dir.join("frame-00001.png"))
+                .unwrap()
+                .starts_with(b"\x89PNG")
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
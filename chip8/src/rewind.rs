@@ -0,0 +1,259 @@
+//! Rewinding through recent save states
+
+use std::collections::VecDeque;
+
+use crate::{Chip8, StateError};
+
+/// Recent save states of an interpreter, to go back in time with.
+///
+/// A snapshot is taken every `interval` frames, keeping the last `capacity`.
+/// Only the newest is stored whole. Each older one is stored as its
+/// difference to the next, compressed, which for most programs is a few
+/// dozen bytes as little memory changes from frame to frame.
+#[derive(Debug)]
+pub struct Rewind {
+    interval: u32,
+    capacity: usize,
+    /// Frames recorded so far, less those rewound
+    frame: u64,
+    /// The newest snapshot and the frame it was taken after
+    newest: Option<(u64, Vec<u8>)>,
+    /// Older snapshots, oldest first, each as the frame it was taken after
+    /// and its difference to the next
+    deltas: VecDeque<(u64, Vec<u8>)>,
+}
+
+impl Rewind {
+    /// Snapshots every `interval` frames, keeping `capacity` of them
+    pub fn new(interval: u32, capacity: usize) -> Self {
+        Rewind {
+            interval: interval.max(1),
+            capacity: capacity.max(1),
+            frame: 0,
+            newest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    pub fn interval(&self) -> u32 {
+        self.interval
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Counts a frame run by `chip8`, taking a snapshot if one is due. Call
+    /// after every frame.
+    pub fn record(&mut self, chip8: &Chip8) {
+        self.frame += 1;
+        if !self.frame.is_multiple_of(u64::from(self.interval)) {
+            return;
+        }
+        let state = chip8.save_state();
+        if let Some((frame, newest)) = self.newest.take() {
+            if newest.len() == state.len() {
+                self.deltas
+                    .push_back((frame, delta_encode(&newest, &state)));
+            } else {
+                // Deltas only apply between states of the same size
+                self.deltas.clear();
+            }
+        }
+        self.newest = Some((self.frame, state));
+        while self.len() > self.capacity {
+            self.deltas.pop_front();
+        }
+    }
+
+    /// Restores the newest snapshot taken at least `frames` frames ago, or
+    /// the oldest one if none is that old, and drops the snapshots after it.
+    /// Returns the number of frames gone back, 0 if there are no snapshots.
+    pub fn rewind(&mut self, chip8: &mut Chip8, frames: u64) -> Result<u64, StateError> {
+        let target = self.frame.saturating_sub(frames);
+        let Some((mut frame, mut state)) = self.newest.take() else {
+            return Ok(0);
+        };
+        while frame > target
+            && let Some((older, delta)) = self.deltas.pop_back()
+        {
+            delta_apply(&mut state, &delta);
+            frame = older;
+        }
+        let result = chip8.load_state(&state);
+        self.newest = Some((frame, state));
+        result?;
+        let rewound = self.frame - frame;
+        self.frame = frame;
+        Ok(rewound)
+    }
+
+    /// Number of snapshots kept
+    pub fn len(&self) -> usize {
+        self.deltas.len() + usize::from(self.newest.is_some())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    /// Bytes taken up by the snapshots
+    pub fn memory_usage(&self) -> usize {
+        let newest = self.newest.as_ref().map_or(0, |(_, state)| state.len());
+        newest
+            + self
+                .deltas
+                .iter()
+                .map(|(_, delta)| delta.len())
+                .sum::<usize>()
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while let Some(&byte) = bytes.get(*pos) {
+        *pos += 1;
+        value |= usize::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    value
+}
+
+/// Encodes `from` XOR `to`, which are the same length, as runs of
+/// unchanged bytes followed by the changed bytes: a varint count of
+/// unchanged bytes, a varint count of changed ones and their XOR values
+fn delta_encode(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < from.len() {
+        let unchanged = from[pos..]
+            .iter()
+            .zip(&to[pos..])
+            .take_while(|(a, b)| a == b)
+            .count();
+        pos += unchanged;
+        if pos == from.len() {
+            break;
+        }
+        // A single unchanged byte costs less to store as a change than
+        // starting a new run
+        let mut end = pos;
+        while end < from.len() && (from[end] != to[end] || from.get(end + 1) != to.get(end + 1)) {
+            end += 1;
+        }
+        write_varint(&mut out, unchanged);
+        write_varint(&mut out, end - pos);
+        out.extend(from[pos..end].iter().zip(&to[pos..end]).map(|(a, b)| a ^ b));
+        pos = end;
+    }
+    out
+}
+
+/// Turns one side of a [`delta_encode`]d pair into the other
+fn delta_apply(state: &mut [u8], delta: &[u8]) {
+    let mut pos = 0;
+    let mut offset = 0;
+    while pos < delta.len() {
+        offset += read_varint(delta, &mut pos);
+        let changed = read_varint(delta, &mut pos);
+        for (byte, xor) in state[offset..offset + changed]
+            .iter_mut()
+            .zip(&delta[pos..pos + changed])
+        {
+            *byte ^= xor;
+        }
+        offset += changed;
+        pos += changed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Quirks;
+
+    const TETRIS: &[u8] = include_bytes!("../../examples/tetris.ch8");
+
+    #[test]
+    fn test_delta_round_trip() {
+        let from = [0u8, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+        let to = [0u8, 1, 9, 3, 9, 5, 6, 7, 8, 0];
+        let delta = delta_encode(&from, &to);
+        // Two runs: 2 unchanged and 3 changed, then 4 unchanged and 1 changed
+        assert_eq!(delta, [2, 3, 2 ^ 9, 0, 4 ^ 9, 4, 1, 9]);
+        let mut state = from;
+        delta_apply(&mut state, &delta);
+        assert_eq!(state, to);
+        delta_apply(&mut state, &delta);
+        assert_eq!(state, from);
+        assert!(delta_encode(&from, &from).is_empty());
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut chip8 = Chip8::new(Quirks::cosmac_vip());
+        chip8.load_rom(TETRIS).unwrap();
+        let mut rewind = Rewind::new(3, 50);
+        let mut states = vec![chip8.save_state()];
+        for _ in 0..300 {
+            chip8.keypress(0x5, states.len() % 40 < 10);
+            chip8.run_frame(15).unwrap();
+            rewind.record(&chip8);
+            states.push(chip8.save_state());
+        }
+        assert_eq!(rewind.len(), 50);
+        // Far less than 50 whole states
+        assert!(
+            rewind.memory_usage() < 2 * states[0].len(),
+            "{} bytes",
+            rewind.memory_usage()
+        );
+
+        // Back to the snapshot after frame 297, then 294
+        assert_eq!(rewind.rewind(&mut chip8, 2), Ok(3));
+        assert_eq!(chip8.save_state(), states[297]);
+        assert_eq!(rewind.rewind(&mut chip8, 3), Ok(3));
+        assert_eq!(chip8.save_state(), states[294]);
+
+        // Running again continues from there
+        chip8.run_frame(15).unwrap();
+        rewind.record(&chip8);
+        chip8.run_frame(15).unwrap();
+        rewind.record(&chip8);
+        assert_eq!(rewind.rewind(&mut chip8, 100), Ok(101));
+        assert_eq!(chip8.save_state(), states[195]);
+
+        // As far back as the snapshots go
+        assert_eq!(rewind.rewind(&mut chip8, 1000), Ok(42));
+        assert_eq!(chip8.save_state(), states[153]);
+        assert_eq!(rewind.len(), 1);
+        assert_eq!(rewind.rewind(&mut chip8, 1000), Ok(0));
+    }
+
+    #[test]
+    fn test_rewind_empty() {
+        let mut chip8 = Chip8::default();
+        let mut rewind = Rewind::new(10, 5);
+        assert!(rewind.is_empty());
+        assert_eq!(rewind.rewind(&mut chip8, 1), Ok(0));
+        rewind.record(&chip8);
+        assert!(rewind.is_empty());
+    }
+}
@@ -0,0 +1,74 @@
+use std::fmt;
+
+/// Source of the random bytes used by 0xCXNN
+pub trait RandomSource: fmt::Debug {
+    fn next_byte(&mut self) -> u8;
+
+    /// Internal state captured by save states. Sources that can't be
+    /// captured keep the default, and won't resume their sequence on load.
+    fn state(&self) -> u64 {
+        0
+    }
+
+    fn set_state(&mut self, _state: u64) {}
+}
+
+/// Small deterministic xorshift64* generator, the default random source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Xorshift64 {
+    state: u64,
+}
+
+impl Xorshift64 {
+    pub fn new(seed: u64) -> Self {
+        // An all-zero state would only ever produce zeros
+        let state = if seed == 0 { 0x9E3779B97F4A7C15 } else { seed };
+        Xorshift64 { state }
+    }
+}
+
+impl Default for Xorshift64 {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl RandomSource for Xorshift64 {
+    fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545F4914F6CDD1D) >> 56) as u8
+    }
+
+    fn state(&self) -> u64 {
+        self.state
+    }
+
+    fn set_state(&mut self, state: u64) {
+        *self = Self::new(state);
+    }
+}
+
+/// Serializes a random source as its captured state, restoring it as a [`Xorshift64`]
+#[cfg(feature = "serde")]
+pub(crate) mod serde_state {
+    use super::{RandomSource, Xorshift64};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    #[allow(clippy::borrowed_box)]
+    pub fn serialize<S: Serializer>(
+        rng: &Box<dyn RandomSource>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(rng.state())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Box<dyn RandomSource>, D::Error> {
+        let mut rng = Xorshift64::default();
+        rng.set_state(u64::deserialize(deserializer)?);
+        Ok(Box::new(rng))
+    }
+}
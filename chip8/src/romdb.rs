@@ -0,0 +1,393 @@
+//! ROM metadata in the format of the community CHIP-8 database,
+//! <https://github.com/chip-8/chip-8-database>
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::json::Json;
+use crate::sha1::sha1;
+use crate::{Keymap, LoadStoreIncrement, Mode, Quirks};
+
+/// The built-in table, covering the ROMs in `examples/`
+const BUILTIN: &str = include_str!("../data/programs.json");
+
+/// What is known about a ROM
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub release: Option<String>,
+    pub description: Option<String>,
+    /// Platforms the ROM runs on by their database ids such as
+    /// `originalChip8` or `xochip`, preferred first
+    pub platforms: Vec<String>,
+    /// Recommended instructions per frame
+    pub tickrate: Option<u32>,
+    /// Quirks differing from a platform's defaults, by platform id and
+    /// database quirk name
+    pub quirky_platforms: BTreeMap<String, BTreeMap<String, bool>>,
+    /// CHIP-8 keys for actions such as `up` or `a`
+    pub keys: BTreeMap<String, u8>,
+}
+
+impl RomInfo {
+    /// The mode and quirks of the first platform this interpreter supports,
+    /// with the ROM's quirk overrides applied
+    pub fn platform(&self) -> Option<(Mode, Quirks)> {
+        self.platforms.iter().find_map(|id| {
+            let (mode, mut quirks) = platform_defaults(id)?;
+            // The database has a quirk each for the two ways of not adding
+            // X + 1 to I, with leaving it unchanged taking precedence
+            let mut increment_by_x = quirks.load_store_increment == LoadStoreIncrement::ByX;
+            let mut leave_i_unchanged =
+                quirks.load_store_increment == LoadStoreIncrement::Unchanged;
+            for (quirk, &value) in self.quirky_platforms.get(id).into_iter().flatten() {
+                match quirk.as_str() {
+                    "shift" => quirks.shift_uses_vy = !value,
+                    "memoryIncrementByX" => increment_by_x = value,
+                    "memoryLeaveIUnchanged" => leave_i_unchanged = value,
+                    "wrap" => quirks.clipping = !value,
+                    "jump" => quirks.jump_uses_vx = value,
+                    "vblank" => quirks.display_wait = value,
+                    "logic" => quirks.vf_reset = value,
+                    _ => {}
+                }
+            }
+            quirks.load_store_increment = match (leave_i_unchanged, increment_by_x) {
+                (true, _) => LoadStoreIncrement::Unchanged,
+                (false, true) => LoadStoreIncrement::ByX,
+                (false, false) => LoadStoreIncrement::ByXPlusOne,
+            };
+            Some((mode, quirks))
+        })
+    }
+
+    /// [`Keymap::qwerty`] plus the arrow keys for the `up`, `down`, `left`
+    /// and `right` actions, space for `a` and enter for `b`
+    pub fn keymap(&self) -> Keymap {
+        let mut keymap = Keymap::qwerty();
+        for (action, host) in [
+            ("up", "Up"),
+            ("down", "Down"),
+            ("left", "Left"),
+            ("right", "Right"),
+            ("a", "Space"),
+            ("b", "Enter"),
+        ] {
+            if let Some(&key) = self.keys.get(action) {
+                keymap.bind(host, key);
+            }
+        }
+        keymap
+    }
+}
+
+/// Mode and quirks of a database platform id
+fn platform_defaults(id: &str) -> Option<(Mode, Quirks)> {
+    match id {
+        "originalChip8" | "hybridVIP" => Some((Mode::Chip8, Quirks::cosmac_vip())),
+        "modernChip8" => Some((
+            Mode::Chip8,
+            Quirks {
+                vf_reset: false,
+                display_wait: false,
+                key_wait_release: false,
+                ..Quirks::cosmac_vip()
+            },
+        )),
+        "chip48" => Some((Mode::Chip8, Quirks::chip48())),
+        "superchip1" | "superchip" => Some((Mode::SuperChip, Quirks::superchip())),
+        "xochip" => Some((Mode::XoChip, Quirks::xochip())),
+        _ => None,
+    }
+}
+
+/// ROM metadata keyed by the SHA-1 of the ROM
+#[derive(Debug, Clone, Default)]
+pub struct RomDatabase {
+    roms: BTreeMap<[u8; 20], RomInfo>,
+}
+
+impl RomDatabase {
+    /// The table built into the library
+    pub fn builtin() -> Self {
+        Self::parse(BUILTIN).expect("the built-in ROM database is valid")
+    }
+
+    /// Reads the `programs.json` file of the CHIP-8 database: an array of
+    /// programs, each with its ROMs keyed by SHA-1
+    pub fn parse(text: &str) -> Result<Self, RomDatabaseError> {
+        let json = Json::parse(text).map_err(RomDatabaseError::Syntax)?;
+        let programs = json
+            .as_array()
+            .ok_or_else(|| invalid("expected an array of programs"))?;
+        let mut roms = BTreeMap::new();
+        for (index, program) in programs.iter().enumerate() {
+            let title = program
+                .get("title")
+                .and_then(Json::as_str)
+                .ok_or_else(|| invalid(format!("program {}: missing title", index)))?;
+            let entries = program
+                .get("roms")
+                .and_then(Json::as_object)
+                .ok_or_else(|| invalid(format!("{}: missing roms", title)))?;
+            let info = RomInfo {
+                title: title.to_string(),
+                authors: program
+                    .get("authors")
+                    .and_then(Json::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Json::as_str)
+                    .map(String::from)
+                    .collect(),
+                release: program
+                    .get("release")
+                    .and_then(Json::as_str)
+                    .map(String::from),
+                description: program
+                    .get("description")
+                    .and_then(Json::as_str)
+                    .map(String::from),
+                ..RomInfo::default()
+            };
+            for (hash, rom) in entries {
+                let hash = parse_sha1(hash)
+                    .ok_or_else(|| invalid(format!("{}: invalid SHA-1 {}", title, hash)))?;
+                let rom = parse_rom(rom, info.clone())
+                    .map_err(|err| invalid(format!("{}: {}", title, err)))?;
+                roms.insert(hash, rom);
+            }
+        }
+        Ok(RomDatabase { roms })
+    }
+
+    /// Metadata of the ROM made of `rom`
+    pub fn lookup(&self, rom: &[u8]) -> Option<&RomInfo> {
+        self.roms.get(&sha1(rom))
+    }
+
+    /// Metadata of the ROM with the hexadecimal SHA-1 `hash`
+    pub fn get(&self, hash: &str) -> Option<&RomInfo> {
+        self.roms.get(&parse_sha1(hash)?)
+    }
+
+    /// Number of ROMs
+    pub fn len(&self) -> usize {
+        self.roms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+}
+
+fn invalid(message: impl Into<String>) -> RomDatabaseError {
+    RomDatabaseError::Invalid(message.into())
+}
+
+fn parse_sha1(hash: &str) -> Option<[u8; 20]> {
+    if hash.len() != 40 || !hash.is_ascii() {
+        return None;
+    }
+    let mut digest = [0; 20];
+    for (byte, pair) in digest.iter_mut().zip(hash.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(digest)
+}
+
+/// Adds the fields of a ROM entry to those of its program
+fn parse_rom(rom: &Json, mut info: RomInfo) -> Result<RomInfo, String> {
+    if rom.as_object().is_none() {
+        return Err("expected a ROM object".to_string());
+    }
+    if let Some(description) = rom.get("description").and_then(Json::as_str) {
+        info.description = Some(description.to_string());
+    }
+    info.platforms = rom
+        .get("platforms")
+        .and_then(Json::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Json::as_str)
+        .map(String::from)
+        .collect();
+    if let Some(tickrate) = rom.get("tickrate") {
+        match tickrate.as_f64() {
+            Some(rate) if rate >= 1.0 && rate <= f64::from(u32::MAX) && rate.fract() == 0.0 => {
+                info.tickrate = Some(rate as u32);
+            }
+            _ => return Err("invalid tickrate".to_string()),
+        }
+    }
+    for (action, key) in rom
+        .get("keys")
+        .and_then(Json::as_object)
+        .into_iter()
+        .flatten()
+    {
+        match key.as_f64() {
+            Some(key) if (0.0..16.0).contains(&key) && key.fract() == 0.0 => {
+                info.keys.insert(action.clone(), key as u8);
+            }
+            _ => return Err(format!("invalid key for {}", action)),
+        }
+    }
+    let platforms = rom.get("quirkyPlatforms").and_then(Json::as_object);
+    for (platform, quirks) in platforms.into_iter().flatten() {
+        let quirks = quirks
+            .as_object()
+            .ok_or_else(|| format!("invalid quirks for {}", platform))?
+            .iter()
+            .filter_map(|(name, value)| Some((name.clone(), value.as_bool()?)))
+            .collect();
+        info.quirky_platforms.insert(platform.clone(), quirks);
+    }
+    Ok(info)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RomDatabaseError {
+    /// The text is not valid JSON, with the byte offset of the error
+    Syntax(usize),
+    /// The JSON does not describe programs as the database does
+    Invalid(String),
+}
+
+impl fmt::Display for RomDatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomDatabaseError::Syntax(offset) => write!(f, "invalid JSON at byte {}", offset),
+            RomDatabaseError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for RomDatabaseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin() {
+        let db = RomDatabase::builtin();
+        assert_eq!(db.len(), 10);
+
+        let tetris = db
+            .lookup(include_bytes!("../../examples/tetris.ch8"))
+            .unwrap();
+        assert_eq!(tetris.title, "Tetris");
+        assert_eq!(tetris.authors, ["Fran Dachille"]);
+        assert_eq!(tetris.tickrate, Some(15));
+        assert_eq!(tetris.platform(), Some((Mode::Chip8, Quirks::cosmac_vip())));
+        let keymap = tetris.keymap();
+        assert_eq!(keymap.get("Left"), Some(0x5));
+        assert_eq!(keymap.get("Space"), Some(0x4));
+        assert_eq!(keymap.get("q"), Some(0x4));
+
+        let scrolling = db.get("477B3E09C43839EA5478B4F0E24536EDAB594F89").unwrap();
+        assert_eq!(scrolling.title, "Scrolling test");
+        assert_eq!(scrolling.platform().unwrap().0, Mode::SuperChip);
+
+        assert!(db.lookup(&[0x12, 0x00]).is_none());
+        assert!(db.get("not a hash").is_none());
+    }
+
+    #[test]
+    fn test_parse() {
+        let db = RomDatabase::parse(
+            r#"[{
+                "title": "Game", "release": "2020",
+                "roms": {"0123456789abcdef0123456789abcdef01234567": {
+                    "platforms": ["megachip8", "superchip", "xochip"],
+                    "quirkyPlatforms": {"superchip": {"shift": true, "wrap": true, "vblank": true}},
+                    "keys": {"up": 10, "player2Up": 3}
+                }}
+            }]"#,
+        )
+        .unwrap();
+        let game = db.get("0123456789abcdef0123456789abcdef01234567").unwrap();
+        assert_eq!(game.title, "Game");
+        assert_eq!(game.release.as_deref(), Some("2020"));
+        assert!(game.authors.is_empty());
+        assert_eq!(game.tickrate, None);
+        assert_eq!(game.keys.get("player2Up"), Some(&3));
+        let (mode, quirks) = game.platform().unwrap();
+        assert_eq!(mode, Mode::SuperChip);
+        assert_eq!(
+            quirks,
+            Quirks {
+                clipping: false,
+                display_wait: true,
+                ..Quirks::superchip()
+            }
+        );
+
+        // CHIP-48 sets memoryIncrementByX, unless the ROM says otherwise
+        let db = RomDatabase::parse(
+            r#"[{
+                "title": "Calculator game",
+                "roms": {
+                    "0123456789abcdef0123456789abcdef01234567": {"platforms": ["chip48"]},
+                    "76543210fedcba9876543210fedcba9876543210": {
+                        "platforms": ["chip48"],
+                        "quirkyPlatforms": {"chip48": {"memoryLeaveIUnchanged": true}}
+                    },
+                    "89abcdef0123456789abcdef0123456789abcdef": {
+                        "platforms": ["chip48"],
+                        "quirkyPlatforms": {"chip48": {"memoryIncrementByX": false}}
+                    },
+                    "fedcba9876543210fedcba9876543210fedcba98": {
+                        "platforms": ["originalChip8"],
+                        "quirkyPlatforms": {"originalChip8": {"memoryIncrementByX": true}}
+                    }
+                }
+            }]"#,
+        )
+        .unwrap();
+        let game = db.get("0123456789abcdef0123456789abcdef01234567").unwrap();
+        let (mode, quirks) = game.platform().unwrap();
+        assert_eq!(mode, Mode::Chip8);
+        assert_eq!(quirks.load_store_increment, LoadStoreIncrement::ByX);
+        let increment = |hash| {
+            let game = db.get(hash).unwrap();
+            game.platform().unwrap().1.load_store_increment
+        };
+        assert_eq!(
+            increment("76543210fedcba9876543210fedcba9876543210"),
+            LoadStoreIncrement::Unchanged
+        );
+        assert_eq!(
+            increment("89abcdef0123456789abcdef0123456789abcdef"),
+            LoadStoreIncrement::ByXPlusOne
+        );
+        assert_eq!(
+            increment("fedcba9876543210fedcba9876543210fedcba98"),
+            LoadStoreIncrement::ByX
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            RomDatabase::parse("[{]").unwrap_err(),
+            RomDatabaseError::Syntax(2)
+        );
+        assert!(matches!(
+            RomDatabase::parse(r#"[{"roms": {}}]"#),
+            Err(RomDatabaseError::Invalid(_))
+        ));
+        assert!(matches!(
+            RomDatabase::parse(r#"[{"title": "A", "roms": {"12": {}}}]"#),
+            Err(RomDatabaseError::Invalid(_))
+        ));
+        assert!(matches!(
+            RomDatabase::parse(
+                r#"[{"title": "A", "roms": {"0123456789abcdef0123456789abcdef01234567": {"keys": {"a": 16}}}}]"#
+            ),
+            Err(RomDatabaseError::Invalid(_))
+        ));
+    }
+}
@@ -0,0 +1,320 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::Chip8;
+
+/// RGB colours for the four pixel values, background first. The last two
+/// are only used by XO-CHIP's second bitplane.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette(pub [[u8; 3]; 4]);
+
+impl Palette {
+    /// `foreground` on `background`, with the default colours for the
+    /// second bitplane
+    pub const fn new(background: [u8; 3], foreground: [u8; 3]) -> Self {
+        Palette([background, foreground, [0xAA; 3], [0x55; 3]])
+    }
+}
+
+/// White on black
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::new([0x00; 3], [0xFF; 3])
+    }
+}
+
+/// A copy of the display, one colour index per pixel row by row
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screenshot {
+    pub width: u16,
+    pub height: u16,
+    pub pixels: Vec<u8>,
+}
+
+impl Screenshot {
+    pub fn capture(chip8: &Chip8) -> Self {
+        Screenshot {
+            width: chip8.width(),
+            height: chip8.height(),
+            pixels: chip8.framebuffer().to_vec(),
+        }
+    }
+
+    /// Writes a PNG with every pixel enlarged to `scale` by `scale` pixels
+    pub fn write_png<W: Write>(&self, mut out: W, scale: u32, palette: &Palette) -> io::Result<()> {
+        let scale = scale.max(1) as usize;
+        let width = usize::from(self.width) * scale;
+        let height = usize::from(self.height) * scale;
+        let (Ok(png_width), Ok(png_height)) = (u32::try_from(width), u32::try_from(height)) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "scale too large for PNG",
+            ));
+        };
+
+        // Rows of palette indices, each led by filter type 0
+        let mut raw = Vec::with_capacity((width + 1) * height);
+        for row in self.pixels.chunks(usize::from(self.width).max(1)) {
+            let start = raw.len();
+            raw.push(0);
+            for &pixel in row {
+                raw.extend(std::iter::repeat_n(pixel & 0x3, scale));
+            }
+            for _ in 1..scale {
+                raw.extend_from_within(start..start + width + 1);
+            }
+        }
+
+        out.write_all(b"\x89PNG\r\n\x1a\n")?;
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&png_width.to_be_bytes());
+        header.extend_from_slice(&png_height.to_be_bytes());
+        // 8 bit palette indices, default compression, filtering and no interlacing
+        header.extend_from_slice(&[8, 3, 0, 0, 0]);
+        write_chunk(&mut out, b"IHDR", &header)?;
+        write_chunk(&mut out, b"PLTE", palette.0.as_flattened())?;
+        write_chunk(&mut out, b"IDAT", &zlib(&raw, width + 1))?;
+        write_chunk(&mut out, b"IEND", &[])?;
+        out.flush()
+    }
+
+    /// Writes a plain PBM (P1) image with one line per row. Lit pixels of
+    /// any colour are black.
+    pub fn write_pbm<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "P1\n{} {}", self.width, self.height)?;
+        for row in self.pixels.chunks(usize::from(self.width).max(1)) {
+            let line: Vec<u8> = row
+                .iter()
+                .map(|&pixel| if pixel == 0 { b'0' } else { b'1' })
+                .collect();
+            out.write_all(&line)?;
+            out.write_all(b"\n")?;
+        }
+        out.flush()
+    }
+
+    /// Writes a PBM file if `path` ends in `.pbm`, a PNG otherwise
+    pub fn save(&self, path: impl AsRef<Path>, scale: u32, palette: &Palette) -> io::Result<()> {
+        let path = path.as_ref();
+        let out = io::BufWriter::new(fs::File::create(path)?);
+        if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("pbm"))
+        {
+            self.write_pbm(out)
+        } else {
+            self.write_png(out, scale, palette)
+        }
+    }
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let crc = crc32(&[kind.as_slice(), data].concat());
+    out.write_all(&crc.to_be_bytes())
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+/// Least significant bit first, as deflate packs its stream
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, count: u32) {
+        self.bits |= value << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes are packed starting from their most significant bit
+    fn write_code(&mut self, code: u32, count: u32) {
+        self.write(code.reverse_bits() >> (32 - count), count);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+        self.bytes
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u32; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u32; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Writes a symbol of the fixed literal/length Huffman code
+fn write_symbol(out: &mut BitWriter, symbol: u16) {
+    let symbol = u32::from(symbol);
+    match symbol {
+        0..=143 => out.write_code(0x30 + symbol, 8),
+        144..=255 => out.write_code(0x190 + symbol - 144, 9),
+        256..=279 => out.write_code(symbol - 256, 7),
+        _ => out.write_code(0xC0 + symbol - 280, 8),
+    }
+}
+
+/// Index of the last entry of `bases` not above `value`
+fn code_index(bases: &[u16], value: u16) -> usize {
+    bases.iter().rposition(|&base| base <= value).unwrap_or(0)
+}
+
+/// Compresses `data` into a zlib stream. Screens are runs of few colours,
+/// often repeating the row above, so only matches one byte or one `stride`
+/// back are searched for.
+fn zlib(data: &[u8], stride: usize) -> Vec<u8> {
+    let mut out = BitWriter {
+        bytes: vec![0x78, 0x01],
+        bits: 0,
+        count: 0,
+    };
+    // A single final block with the fixed Huffman codes
+    out.write(0b1, 1);
+    out.write(0b01, 2);
+    let distances = [1, stride]
+        .into_iter()
+        .filter(|&d| (1..=32768).contains(&d));
+    let distances: Vec<usize> = distances.collect();
+    let mut pos = 0;
+    while pos < data.len() {
+        let (length, distance) = distances
+            .iter()
+            .filter(|&&distance| distance <= pos)
+            .map(|&distance| {
+                let length = data[pos..]
+                    .iter()
+                    .zip(&data[pos - distance..])
+                    .take(258)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                (length, distance)
+            })
+            .max()
+            .unwrap_or((0, 0));
+        if length < 3 {
+            write_symbol(&mut out, u16::from(data[pos]));
+            pos += 1;
+            continue;
+        }
+        let (length, distance) = (length as u16, distance as u16);
+        let index = code_index(&LENGTH_BASE, length);
+        write_symbol(&mut out, 257 + index as u16);
+        out.write(u32::from(length - LENGTH_BASE[index]), LENGTH_EXTRA[index]);
+        let index = code_index(&DISTANCE_BASE, distance);
+        out.write_code(index as u32, 5);
+        out.write(
+            u32::from(distance - DISTANCE_BASE[index]),
+            DISTANCE_EXTRA[index],
+        );
+        pos += usize::from(length);
+    }
+    write_symbol(&mut out, 256);
+    let mut bytes = out.finish();
+    bytes.extend_from_slice(&adler32(data).to_be_bytes());
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logo() -> Screenshot {
+        // LD I, 0x50 (font "0"); DRW V0, V0, 5
+        let mut chip8 = Chip8::default();
+        chip8.load_rom(&[0xA0, 0x50, 0xD0, 0x05]).unwrap();
+        chip8.run_frame(2).unwrap();
+        Screenshot::capture(&chip8)
+    }
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+    }
+
+    #[test]
+    fn test_pbm() {
+        let mut pbm = Vec::new();
+        logo().write_pbm(&mut pbm).unwrap();
+        let pbm = String::from_utf8(pbm).unwrap();
+        let lines: Vec<&str> = pbm.lines().collect();
+        assert_eq!(lines.len(), 34);
+        assert_eq!(lines[..2], ["P1", "64 32"]);
+        assert_eq!(&lines[2][..8], "11110000");
+        assert_eq!(&lines[3][..8], "10010000");
+        assert_eq!(lines[7], "0".repeat(64));
+    }
+
+    #[test]
+    fn test_png() {
+        let mut png = Vec::new();
+        let palette = Palette::new([0x10, 0x20, 0x30], [0xF0, 0xE0, 0xD0]);
+        logo().write_png(&mut png, 4, &palette).unwrap();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..20], &256u32.to_be_bytes());
+        assert_eq!(&png[20..24], &128u32.to_be_bytes());
+        assert_eq!(&png[24..29], &[8, 3, 0, 0, 0]);
+        assert_eq!(&png[37..41], b"PLTE");
+        assert_eq!(&png[41..47], &[0x10, 0x20, 0x30, 0xF0, 0xE0, 0xD0]);
+        assert!(png.ends_with(b"IEND\xae\x42\x60\x82"));
+        // The mostly blank screen compresses to a small fraction of its
+        // 256 * 128 pixels
+        assert!(png.len() < 1024, "{} bytes", png.len());
+    }
+
+    #[test]
+    fn test_zlib_matches() {
+        // A run, then a repeat of the previous "row"
+        let data = [[0u8; 5].as_slice(), &[1, 2, 3, 4, 5], &[1, 2, 3, 4, 5]].concat();
+        let stream = zlib(&data, 5);
+        assert_eq!(&stream[..2], &[0x78, 0x01]);
+        assert!(stream.len() < data.len() + 6);
+        assert!(stream.ends_with(&adler32(&data).to_be_bytes()));
+    }
+}
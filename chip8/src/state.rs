@@ -0,0 +1,414 @@
+//! Binary save states, see [`Chip8::save_state`] for the format.
+
+use std::fmt;
+
+use crate::{
+    Chip8, HIRES_HEIGHT, HIRES_WIDTH, LoadStoreIncrement, MEMORY_SIZE, Mode, Quirks, XO_MEMORY_SIZE,
+};
+
+const MAGIC: &[u8; 4] = b"C8ST";
+
+/// Version of the save state format written by [`Chip8::save_state`]
+pub const STATE_VERSION: u16 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The blob does not start with the save state magic bytes
+    BadMagic,
+    /// The blob was written by an incompatible version of the format
+    UnsupportedVersion(u16),
+    /// The blob ended before all fields were read
+    Truncated,
+    /// The checksum does not match the contents of the blob
+    ChecksumMismatch,
+    /// A field holds a value that no running interpreter can be in
+    InvalidField(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::ChecksumMismatch => write!(f, "save state checksum mismatch"),
+            StateError::InvalidField(field) => write!(f, "invalid save state field: {}", field),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+pub(crate) fn quirks_to_bits(quirks: &Quirks) -> u8 {
+    u8::from(quirks.shift_uses_vy)
+        | u8::from(quirks.load_store_increment != LoadStoreIncrement::Unchanged) << 1
+        | u8::from(quirks.vf_reset) << 2
+        | u8::from(quirks.clipping) << 3
+        | u8::from(quirks.jump_uses_vx) << 4
+        | u8::from(quirks.display_wait) << 5
+        | u8::from(quirks.key_wait_release) << 6
+        | u8::from(quirks.load_store_increment == LoadStoreIncrement::ByX) << 7
+}
+
+pub(crate) fn quirks_from_bits(bits: u8) -> Quirks {
+    Quirks {
+        shift_uses_vy: bits & 0x01 != 0,
+        load_store_increment: match (bits & 0x02 != 0, bits & 0x80 != 0) {
+            (false, _) => LoadStoreIncrement::Unchanged,
+            (true, false) => LoadStoreIncrement::ByXPlusOne,
+            (true, true) => LoadStoreIncrement::ByX,
+        },
+        vf_reset: bits & 0x04 != 0,
+        clipping: bits & 0x08 != 0,
+        jump_uses_vx: bits & 0x10 != 0,
+        display_wait: bits & 0x20 != 0,
+        key_wait_release: bits & 0x40 != 0,
+    }
+}
+
+pub(crate) fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811C9DC5, |hash, &byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x01000193)
+    })
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        let end = self.pos.checked_add(len).ok_or(StateError::Truncated)?;
+        let bytes = self.data.get(self.pos..end).ok_or(StateError::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+}
+
+impl Chip8 {
+    /// Captures the complete machine state.
+    ///
+    /// A save state is a little-endian blob laid out as follows:
+    ///
+    /// | Size  | Field                                                  |
+    /// |-------|--------------------------------------------------------|
+    /// | 4     | Magic bytes `C8ST`                                     |
+    /// | 2     | Format version, [`STATE_VERSION`]                      |
+    /// | 1     | Mode: 0 = CHIP-8, 1 = SUPER-CHIP, 2 = XO-CHIP          |
+    /// | 1     | Quirks, one bit per field in declaration order, and    |
+    /// |       | bit 7 set when I is incremented by X only              |
+    /// | 4 + N | Memory length N followed by the memory contents        |
+    /// | 16    | V0 through VF                                          |
+    /// | 2     | I                                                      |
+    /// | 2     | PC                                                     |
+    /// | 32    | Stack, 16 entries                                      |
+    /// | 1     | SP                                                     |
+    /// | 1     | Delay timer                                            |
+    /// | 1     | Sound timer                                            |
+    /// | 16    | Keypad, one byte per key                               |
+    /// | 2     | Keys pressed while waiting for a key, one bit per key  |
+    /// | 2     | Keys released while waiting for a key                  |
+    /// | 16    | RPL user flags                                         |
+    /// | 1     | Bits 0-3: hires, waiting for vblank, halted, key wait  |
+    /// | 1     | Selected bitplanes                                     |
+    /// | 16    | Audio pattern buffer                                   |
+    /// | 1     | Pitch                                                  |
+    /// | 8     | Random source state                                    |
+    /// | 4 + N | Framebuffer length N followed by the framebuffer       |
+    /// | 4     | FNV-1a checksum of all the preceding bytes             |
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.memory.len() + self.gfx.len() + 192);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&STATE_VERSION.to_le_bytes());
+        out.push(match self.mode {
+            Mode::Chip8 => 0,
+            Mode::SuperChip => 1,
+            Mode::XoChip => 2,
+        });
+        out.push(quirks_to_bits(&self.quirks));
+        out.extend_from_slice(&(self.memory.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.memory);
+        out.extend_from_slice(&self.v);
+        out.extend_from_slice(&self.i.to_le_bytes());
+        out.extend_from_slice(&self.pc.to_le_bytes());
+        for addr in self.stack {
+            out.extend_from_slice(&addr.to_le_bytes());
+        }
+        out.push(self.sp);
+        out.push(self.delay_timer);
+        out.push(self.sound_timer);
+        out.extend_from_slice(&self.keypad);
+        out.extend_from_slice(&self.key_presses.to_le_bytes());
+        out.extend_from_slice(&self.key_releases.to_le_bytes());
+        out.extend_from_slice(&self.rpl_flags);
+        out.push(
+            u8::from(self.hires)
+                | u8::from(self.waiting_vblank) << 1
+                | u8::from(self.halted) << 2
+                | u8::from(self.waiting_key) << 3,
+        );
+        out.push(self.planes);
+        out.extend_from_slice(&self.audio_pattern);
+        out.push(self.pitch);
+        out.extend_from_slice(&self.rng.state().to_le_bytes());
+        out.extend_from_slice(&(self.gfx.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.gfx);
+        let checksum = fnv1a(&out);
+        out.extend_from_slice(&checksum.to_le_bytes());
+        out
+    }
+
+    /// Restores a state captured by [`Chip8::save_state`]. On error the
+    /// machine is left untouched.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut reader = Reader { data, pos: 0 };
+        if reader
+            .bytes(MAGIC.len())
+            .map_err(|_| StateError::BadMagic)?
+            != MAGIC
+        {
+            return Err(StateError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let payload_len = data.len().checked_sub(4).ok_or(StateError::Truncated)?;
+        let (payload, checksum) = data.split_at(payload_len);
+        if payload_len < reader.pos {
+            return Err(StateError::Truncated);
+        }
+        if fnv1a(payload) != u32::from_le_bytes(checksum.try_into().unwrap()) {
+            return Err(StateError::ChecksumMismatch);
+        }
+        let mut reader = Reader {
+            data: payload,
+            pos: reader.pos,
+        };
+
+        let mode = match reader.u8()? {
+            0 => Mode::Chip8,
+            1 => Mode::SuperChip,
+            2 => Mode::XoChip,
+            _ => return Err(StateError::InvalidField("mode")),
+        };
+        let quirks = quirks_from_bits(reader.u8()?);
+        let mut restored = Chip8::with_mode(mode, quirks);
+
+        let memory_len = reader.u32()? as usize;
+        restored.memory = reader.bytes(memory_len)?.to_vec();
+        restored.v = reader.array()?;
+        restored.i = reader.u16()?;
+        restored.pc = reader.u16()?;
+        for entry in restored.stack.iter_mut() {
+            *entry = reader.u16()?;
+        }
+        restored.sp = reader.u8()?;
+        restored.delay_timer = reader.u8()?;
+        restored.sound_timer = reader.u8()?;
+        restored.keypad = reader.array()?;
+        restored.key_presses = reader.u16()?;
+        restored.key_releases = reader.u16()?;
+        restored.rpl_flags = reader.array()?;
+        let flags = reader.u8()?;
+        restored.hires = flags & 0x01 != 0;
+        restored.waiting_vblank = flags & 0x02 != 0;
+        restored.halted = flags & 0x04 != 0;
+        restored.waiting_key = flags & 0x08 != 0;
+        restored.planes = reader.u8()?;
+        restored.audio_pattern = reader.array()?;
+        restored.pitch = reader.u8()?;
+        let rng_state = u64::from_le_bytes(reader.array()?);
+        let gfx_len = reader.u32()? as usize;
+        restored.gfx = reader.bytes(gfx_len)?.to_vec();
+        if reader.pos != payload.len() {
+            return Err(StateError::InvalidField("trailing data"));
+        }
+        restored.validate()?;
+
+        // Keep the embedder's random source, only its state is restored
+        std::mem::swap(&mut restored.rng, &mut self.rng);
+        restored.rng.set_state(rng_state);
+        restored.observer = self.observer.take();
+        restored.set_decode_cache(self.decode_cache_enabled());
+        *self = restored;
+        Ok(())
+    }
+
+    /// Checks the fields that the interpreter indexes with or expects a
+    /// size of, so that a restored machine can't panic
+    fn validate(&self) -> Result<(), StateError> {
+        let memory_len = match self.mode {
+            Mode::Chip8 | Mode::SuperChip => MEMORY_SIZE,
+            Mode::XoChip => XO_MEMORY_SIZE,
+        };
+        if self.memory.len() != memory_len {
+            return Err(StateError::InvalidField("memory"));
+        }
+        if usize::from(self.sp) > self.stack.len() {
+            return Err(StateError::InvalidField("sp"));
+        }
+        if self.planes > 0x3 {
+            return Err(StateError::InvalidField("planes"));
+        }
+        if self.gfx.len() != HIRES_WIDTH as usize * HIRES_HEIGHT as usize {
+            return Err(StateError::InvalidField("framebuffer"));
+        }
+        Ok(())
+    }
+}
+
+/// The derived implementations, with deserialized machines checked as
+/// [`Chip8::load_state`] checks restored ones
+#[cfg(feature = "serde")]
+impl serde::Serialize for Chip8 {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Chip8::serialize(self, serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Chip8 {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let chip8 = Chip8::deserialize(deserializer)?;
+        chip8.validate().map_err(serde::de::Error::custom)?;
+        Ok(chip8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Xorshift64;
+
+    fn running_chip8() -> Chip8 {
+        let mut chip8 = Chip8::with_mode(Mode::SuperChip, Quirks::superchip());
+        // HIGH; LD V0, 0x20; LD DT, V0; CALL 0x20A; JP 0x208; LD I, 0x50; DRW V0, V0, 5; RET
+        chip8
+            .load_rom(&[
+                0x00, 0xFF, 0x60, 0x20, 0xF0, 0x15, 0x22, 0x0A, 0x12, 0x08, 0xA0, 0x50, 0xD0, 0x05,
+                0x00, 0xEE,
+            ])
+            .unwrap();
+        chip8.keypress(0x3, true);
+        chip8.run_frame(6).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let chip8 = running_chip8();
+        let state = chip8.save_state();
+        let mut restored = Chip8::default();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.mode, Mode::SuperChip);
+        assert_eq!(restored.quirks, Quirks::superchip());
+        assert_eq!(restored.pc, chip8.pc);
+        assert_eq!(restored.sp, 1);
+        assert_eq!(restored.delay_timer, 0x1F);
+        assert_eq!(restored.keypad[0x3], 1);
+        assert_eq!(restored.pixel_at(0x20, 0x20), 1);
+
+        for quirks in [
+            Quirks::cosmac_vip(),
+            Quirks::chip48(),
+            Quirks::superchip(),
+            Quirks::xochip(),
+        ] {
+            assert_eq!(quirks_from_bits(quirks_to_bits(&quirks)), quirks);
+        }
+    }
+
+    #[test]
+    fn test_state_rejects_bad_blobs() {
+        let state = running_chip8().save_state();
+        let mut chip8 = Chip8::default();
+
+        assert_eq!(chip8.load_state(b"nope"), Err(StateError::BadMagic));
+        assert_eq!(chip8.load_state(&state[..6]), Err(StateError::Truncated));
+
+        let mut version = state.clone();
+        version[4] = 0xFF;
+        assert_eq!(
+            chip8.load_state(&version),
+            Err(StateError::UnsupportedVersion(0x00FF))
+        );
+
+        let mut corrupt = state.clone();
+        corrupt[0x300] ^= 0xFF;
+        assert_eq!(
+            chip8.load_state(&corrupt),
+            Err(StateError::ChecksumMismatch)
+        );
+
+        assert_eq!(
+            chip8.load_state(&state[..state.len() - 1]),
+            Err(StateError::ChecksumMismatch)
+        );
+        // The machine is untouched after a failed load
+        assert_eq!(chip8.save_state(), Chip8::default().save_state());
+    }
+
+    #[test]
+    fn test_state_resumes_random_sequence() {
+        let mut chip8 =
+            Chip8::with_rng(Mode::Chip8, Quirks::default(), Box::new(Xorshift64::new(7)));
+        // RND V0, 0xFF; JP 0x200
+        chip8.load_rom(&[0xC0, 0xFF, 0x12, 0x00]).unwrap();
+        chip8.run_frame(10).unwrap();
+        let state = chip8.save_state();
+        chip8.run_frame(10).unwrap();
+
+        let mut restored = Chip8::default();
+        restored.load_state(&state).unwrap();
+        restored.run_frame(10).unwrap();
+        assert_eq!(restored.save_state(), chip8.save_state());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_state_json() {
+        let chip8 = running_chip8();
+        let json = serde_json::to_string(&chip8).unwrap();
+        let restored: Chip8 = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.save_state(), chip8.save_state());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_state_json_rejects_invalid_fields() {
+        let mut chip8 = Chip8::default();
+        // RET
+        chip8.load_rom(&[0x00, 0xEE]).unwrap();
+        let mut json: serde_json::Value = serde_json::to_value(&chip8).unwrap();
+        json["sp"] = 200.into();
+        let err = serde_json::from_value::<Chip8>(json).unwrap_err();
+        assert!(err.to_string().contains("sp"), "{}", err);
+
+        let mut json: serde_json::Value = serde_json::to_value(&chip8).unwrap();
+        json["memory"] = serde_json::Value::Array(Vec::new());
+        assert!(serde_json::from_value::<Chip8>(json).is_err());
+    }
+}
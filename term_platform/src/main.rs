@@ -1,21 +1,34 @@
-use chip8::Chip8;
+use chip8::{AssembleError, Buzzer, Chip8, GifRecorder, Instruction, KEYMAP_PRESETS, Keymap, Mode, Movie, MoviePlayer, MovieRecorder, Observer, Palette, PngSequenceRecorder, Quirks, Rewind, RomDatabase, RomInfo, Screenshot, Xorshift64, compile_octo, write_wav};
 use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags};
 use crossterm::{ExecutableCommand, QueueableCommand, cursor, event, style, terminal};
 use std::io::{self, Stdout, Write};
 use std::{thread, time, fs};
 use std::fmt::Display;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use clap::{Parser, ValueEnum};
 
 pub struct Settings {
-    pub debug: bool,
     pub cycles: Option<u32>,
-    pub fps: u64
+    pub fps: u64,
+    pub ipf: u32,
+    /// Record the buzzer to this WAV file
+    pub wav: Option<PathBuf>,
+    pub keymap: Keymap,
+    /// Save a screenshot to the file once this many frames have run
+    pub screenshot_after: Option<(u64, PathBuf)>,
+    /// Record the session from the start to this GIF file or PNG directory
+    pub record: Option<PathBuf>,
+    /// Record the keys into this movie, saved to the file on exit
+    pub record_input: Option<(PathBuf, Movie)>,
+    /// Play the keys of this movie instead of reading the keyboard
+    pub replay_input: Option<Movie>,
+    /// How far back the rewind key can go, 0 to disable it
+    pub rewind_seconds: u64,
 }
 
 pub trait Platform {
     fn new(chip8: Chip8, settings: Settings) -> Self;
-    fn load(&mut self, rom: Vec<u8>);
+    fn load(&mut self, rom: Vec<u8>) -> Result<(), Box<dyn std::error::Error>>;
     fn init(&mut self) -> Result<(), Box<dyn std::error::Error>>;
     fn update(&mut self) -> Result<(), Box<dyn std::error::Error>>;
     fn render(&mut self) -> Result<(), Box<dyn std::error::Error>>;
@@ -36,12 +49,160 @@ impl Display for PlatformType {
     }
 }
 
+#[derive(ValueEnum, Clone, Debug)]
+pub enum ModeType {
+    Chip8,
+    Schip,
+    Xochip,
+}
+
+impl Display for ModeType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModeType::Chip8 => write!(f, "chip8"),
+            ModeType::Schip => write!(f, "schip"),
+            ModeType::Xochip => write!(f, "xochip"),
+        }
+    }
+}
+
+impl From<ModeType> for Mode {
+    fn from(mode: ModeType) -> Self {
+        match mode {
+            ModeType::Chip8 => Mode::Chip8,
+            ModeType::Schip => Mode::SuperChip,
+            ModeType::Xochip => Mode::XoChip,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+pub enum QuirksPreset {
+    Vip,
+    Chip48,
+    Schip,
+    Xochip,
+}
+
+impl Display for QuirksPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QuirksPreset::Vip => write!(f, "vip"),
+            QuirksPreset::Chip48 => write!(f, "chip48"),
+            QuirksPreset::Schip => write!(f, "schip"),
+            QuirksPreset::Xochip => write!(f, "xochip"),
+        }
+    }
+}
+
+impl From<QuirksPreset> for Quirks {
+    fn from(preset: QuirksPreset) -> Self {
+        match preset {
+            QuirksPreset::Vip => Quirks::cosmac_vip(),
+            QuirksPreset::Chip48 => Quirks::chip48(),
+            QuirksPreset::Schip => Quirks::superchip(),
+            QuirksPreset::Xochip => Quirks::xochip(),
+        }
+    }
+}
+
+/// Writes the instruction trace to a file, as the display owns the terminal
+#[derive(Debug)]
+struct TraceObserver {
+    out: io::BufWriter<fs::File>,
+}
+
+impl Observer for TraceObserver {
+    fn on_instruction(&mut self, pc: u16, ins: &Instruction) {
+        let _ = writeln!(self.out, "{:#06x}  {}", pc, ins);
+    }
+
+    fn on_unknown_opcode(&mut self, pc: u16, opcode: u16) {
+        let _ = writeln!(self.out, "{:#06x}  unknown opcode {:#06x}", pc, opcode);
+    }
+}
+
+/// Sample rate of recorded audio
+const WAV_SAMPLE_RATE: u32 = 44100;
+
+/// Size in pixels of a CHIP-8 pixel in PNG screenshots
+const SCREENSHOT_SCALE: u32 = 8;
+
+/// Size in pixels of a SUPER-CHIP high resolution pixel in recordings
+const RECORD_SCALE: u32 = 4;
+
+/// Frames between rewind snapshots, so holding the rewind key plays back
+/// at this many times normal speed
+const REWIND_INTERVAL: u32 = 2;
+
+/// A session being recorded, see `--record`
+enum Recording {
+    Gif(GifRecorder<io::BufWriter<fs::File>>),
+    Png(PngSequenceRecorder),
+}
+
+impl Recording {
+    /// Records a GIF if `path` ends in .gif, else PNGs into the directory `path`
+    fn start(path: &Path, fps: u64) -> Result<Recording, Box<dyn std::error::Error>> {
+        let fps = u32::try_from(fps)?;
+        let palette = Palette::default();
+        if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gif")) {
+            let out = io::BufWriter::new(fs::File::create(path)?);
+            Ok(Recording::Gif(GifRecorder::new(out, fps, RECORD_SCALE, &palette)?))
+        } else {
+            Ok(Recording::Png(PngSequenceRecorder::new(path, fps, RECORD_SCALE, &palette)?))
+        }
+    }
+
+    fn capture(&mut self, chip8: &Chip8) -> io::Result<()> {
+        match self {
+            Recording::Gif(gif) => gif.capture(chip8),
+            Recording::Png(png) => png.capture(chip8),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Recording::Gif(gif) => gif.finish().map(drop),
+            Recording::Png(png) => png.finish().map(drop),
+        }
+    }
+}
+
+/// Where the keys come from
+enum Input {
+    Keyboard,
+    /// The keyboard, recorded into an input movie saved on cleanup
+    Record(PathBuf, MovieRecorder),
+    Replay(MoviePlayer),
+}
+
+/// Buzzer samples collected over a run, written out on cleanup
+struct WavRecorder {
+    path: PathBuf,
+    buzzer: Buzzer,
+    samples: Vec<f32>,
+}
+
 pub struct TerminalPlatform {
     stdout: Stdout,
     chip8: Chip8,
     settings: Settings,
     target_ft: time::Duration,
     running: bool,
+    /// Resolution last drawn, the screen is cleared when it changes
+    screen_size: (u16, u16),
+    wav: Option<WavRecorder>,
+    /// Frames run so far
+    frame: u64,
+    recording: Option<Recording>,
+    input: Input,
+    rewind: Option<Rewind>,
+    /// Whether the rewind key is held
+    rewinding: bool,
+    /// Errors that didn't end the session, printed once the terminal is
+    /// restored
+    warnings: Vec<String>,
 }
 
 impl TerminalPlatform {
@@ -55,7 +216,12 @@ impl TerminalPlatform {
         Ok(())
     }
 
-    fn handle_event(&mut self, ev: event::Event) {
+    fn screenshot(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        Screenshot::capture(&self.chip8).save(path, SCREENSHOT_SCALE, &Palette::default())?;
+        Ok(())
+    }
+
+    fn handle_event(&mut self, ev: event::Event) -> Result<(), Box<dyn std::error::Error>> {
         match ev {
             Event::Key(KeyEvent {
                 kind: KeyEventKind::Press,
@@ -64,61 +230,126 @@ impl TerminalPlatform {
             }) => {
                 self.running = false;
             }
-            Event::Key(
-                key_event @ KeyEvent {
-                    // kind: KeyEventKind::Press | KeyEventKind::Release,
-                    code: KeyCode::Char(c),
-                    ..
-                },
-            ) => {
-                // if !key_event.is_press() {
-                //     panic!("Key event: {:?}", key_event);
-                // }
-                if let Some(k) = ch_to_key(c) {
-                    self.chip8.keypress(k, key_event.is_press());
+            Event::Key(KeyEvent {
+                kind: KeyEventKind::Press,
+                code: KeyCode::F(12),
+                ..
+            }) => {
+                let path = PathBuf::from(format!("screenshot-{}.png", self.frame));
+                if let Err(err) = self.screenshot(&path) {
+                    self.warnings.push(format!("{}: {}", path.display(), err));
+                }
+            }
+            Event::Key(KeyEvent {
+                kind: KeyEventKind::Press,
+                code: KeyCode::F(9),
+                ..
+            }) => match self.recording.take() {
+                Some(recording) => {
+                    if let Err(err) = recording.finish() {
+                        self.warnings.push(format!("failed to save recording: {}", err));
+                    }
+                }
+                None => {
+                    let path = PathBuf::from(format!("recording-{}.gif", self.frame));
+                    match Recording::start(&path, self.settings.fps) {
+                        Ok(recording) => self.recording = Some(recording),
+                        Err(err) => self.warnings.push(format!("{}: {}", path.display(), err)),
+                    }
+                }
+            },
+            Event::Key(KeyEvent {
+                kind,
+                code: KeyCode::F(8),
+                ..
+            }) => {
+                self.rewinding = kind != KeyEventKind::Release;
+            }
+            Event::Key(key_event) => {
+                let key = key_name(key_event.code)
+                    .and_then(|name| self.settings.keymap.get(&name));
+                if let Some(k) = key {
+                    let (key, pressed) = (usize::from(k), key_event.is_press());
+                    match &mut self.input {
+                        Input::Keyboard => self.chip8.keypress(key, pressed),
+                        Input::Record(_, recorder) => recorder.keypress(key, pressed),
+                        Input::Replay(_) => (),
+                    }
                 }
             }
             _ => (),
         }
+        Ok(())
     }
 }
 
 impl Platform for TerminalPlatform {
-    fn new(chip8: Chip8, settings: Settings) -> TerminalPlatform {
+    fn new(chip8: Chip8, mut settings: Settings) -> TerminalPlatform {
         let target_ft = time::Duration::from_micros(1_000_000 / settings.fps);
+        let wav = settings.wav.clone().map(|path| WavRecorder {
+            path,
+            buzzer: Buzzer::new(WAV_SAMPLE_RATE),
+            samples: Vec::new(),
+        });
+        let input = match (settings.record_input.take(), settings.replay_input.take()) {
+            (_, Some(movie)) => Input::Replay(MoviePlayer::new(movie)),
+            (Some((path, movie)), None) => Input::Record(path, MovieRecorder::new(movie)),
+            (None, None) => Input::Keyboard,
+        };
+        // Going back in time would desynchronize an input movie
+        let snapshots = settings.rewind_seconds * settings.fps / u64::from(REWIND_INTERVAL);
+        let rewind = (matches!(input, Input::Keyboard) && snapshots > 0)
+            .then(|| Rewind::new(REWIND_INTERVAL, snapshots as usize));
         TerminalPlatform {
             stdout: io::stdout(),
             chip8,
             settings,
             target_ft,
             running: false,
+            screen_size: (0, 0),
+            wav,
+            frame: 0,
+            recording: None,
+            input,
+            rewind,
+            rewinding: false,
+            warnings: Vec::new(),
         }
     }
 
     fn render(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.stdout
-            .queue(terminal::Clear(terminal::ClearType::All))?;
-        for y in 0..32 {
-            for x in 0..64 {
-                let pixel = self.chip8.pixel_at(x, y);
-                self.stdout
-                    .queue(cursor::MoveTo(x as u16, y as u16))?;
-                if pixel == 1 {
-                    self.stdout.queue(style::Print("█"))?;
-                } else {
-                    self.stdout.queue(style::Print(" "))?;
-                }
-            }
-            self.stdout.queue(style::Print("\n")).unwrap();
+        let width = self.chip8.width();
+        let size = (width, self.chip8.height());
+        if size != self.screen_size {
+            self.screen_size = size;
+            self.stdout
+                .queue(terminal::Clear(terminal::ClearType::All))?;
+        }
+        // Only rows that changed since the last frame are printed again
+        let rows: Vec<u16> = self.chip8.take_dirty_rows().collect();
+        if rows.is_empty() {
+            return Ok(());
         }
-        self.stdout.flush().unwrap();
+        let framebuffer = self.chip8.framebuffer();
+        for y in rows {
+            let start = usize::from(y) * usize::from(width);
+            let line: String = framebuffer[start..start + usize::from(width)]
+                .iter()
+                .map(|pixel| match pixel {
+                    0 => ' ',
+                    1 => '█',
+                    2 => '▒',
+                    _ => '▓',
+                })
+                .collect();
+            self.stdout.queue(cursor::MoveTo(0, y))?;
+            self.stdout.queue(style::Print(line))?;
+        }
+        self.stdout.flush()?;
         Ok(())
     }
 
     fn init(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        if self.settings.debug {
-            self.chip8.enable_debug();
-        }
         // self.chip8.memory[0x1FF] = 1; // For test 4
 
         terminal::enable_raw_mode()?;
@@ -127,6 +358,9 @@ impl Platform for TerminalPlatform {
         self.stdout.execute(PushKeyboardEnhancementFlags(
             KeyboardEnhancementFlags::REPORT_EVENT_TYPES,
         ))?;
+        if let Some(path) = &self.settings.record {
+            self.recording = Some(Recording::start(path, self.settings.fps)?);
+        }
         Ok(())
     }
 
@@ -136,19 +370,82 @@ impl Platform for TerminalPlatform {
         self.stdout.execute(terminal::LeaveAlternateScreen)?;
         terminal::disable_raw_mode()?;
         // dbg!(&self.chip8);
+        // Attempt every output before reporting what went wrong
+        if let Some(recording) = self.recording.take()
+            && let Err(err) = recording.finish()
+        {
+            self.warnings.push(format!("failed to save recording: {}", err));
+        }
+        if let Input::Record(path, recorder) = &self.input
+            && let Err(err) = fs::write(path, recorder.movie().to_bytes())
+        {
+            self.warnings.push(format!("{}: {}", path.display(), err));
+        }
+        if let Some(wav) = &self.wav {
+            let written = fs::File::create(&wav.path)
+                .and_then(|file| write_wav(io::BufWriter::new(file), WAV_SAMPLE_RATE, &wav.samples));
+            if let Err(err) = written {
+                self.warnings.push(format!("{}: {}", wav.path.display(), err));
+            }
+        }
+        for warning in self.warnings.drain(..) {
+            eprintln!("{}", warning);
+        }
         Ok(())
     }
 
-    fn load(&mut self, rom: Vec<u8>) {
-        self.chip8.load_rom(&rom);
+    fn load(&mut self, rom: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+        self.chip8.load_rom(&rom)?;
+        Ok(())
     }
 
     fn update(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        if event::poll(time::Duration::from_millis(1))? {
+        while event::poll(time::Duration::ZERO)? {
             let ev = event::read()?;
-            self.handle_event(ev);
+            self.handle_event(ev)?;
+        }
+        if self.rewinding
+            && let Some(rewind) = &mut self.rewind
+        {
+            // Snapshots hold the keys held when they were taken, not now
+            let held = *self.chip8.state().keypad;
+            let rewound = rewind.rewind(&mut self.chip8, 1)?;
+            self.frame = self.frame.saturating_sub(rewound);
+            for (key, &pressed) in held.iter().enumerate() {
+                self.chip8.keypress(key, pressed != 0);
+            }
+            return Ok(());
+        }
+        match &mut self.input {
+            Input::Keyboard => (),
+            Input::Record(_, recorder) => recorder.begin_frame(&mut self.chip8),
+            Input::Replay(player) => {
+                if !player.begin_frame(&mut self.chip8) {
+                    self.running = false;
+                    return Ok(());
+                }
+            }
+        }
+        self.chip8.run_frame(self.settings.ipf)?;
+        self.frame += 1;
+        if let Some(rewind) = &mut self.rewind {
+            rewind.record(&self.chip8);
+        }
+        if let Some(recording) = &mut self.recording
+            && let Err(err) = recording.capture(&self.chip8)
+        {
+            self.warnings.push(format!("recording stopped: {}", err));
+            self.recording = None;
+        }
+        if let Some((frames, path)) = &self.settings.screenshot_after
+            && *frames == self.frame
+        {
+            self.screenshot(path)?;
+        }
+        if let Some(wav) = &mut self.wav {
+            let frame = wav.buzzer.frame(&self.chip8);
+            wav.samples.extend(frame);
         }
-        self.chip8.tick();
         Ok(())
     }
 
@@ -156,14 +453,14 @@ impl Platform for TerminalPlatform {
         self.running = true;
         match self.settings.cycles {
             None => loop {
-                if !self.running {
+                if !self.running || self.chip8.is_halted() {
                     break;
                 }
                 self.cycle()?;
             },
             Some(cycles) => {
                 for _ in 0..cycles {
-                    if !self.running {
+                    if !self.running || self.chip8.is_halted() {
                         break;
                     }
                     self.cycle()?;
@@ -174,33 +471,51 @@ impl Platform for TerminalPlatform {
     }
 }
 
-fn ch_to_key(c: char) -> Option<usize> {
-    match c {
-        '1' => Some(0x1),
-        '2' => Some(0x2),
-        '3' => Some(0x3),
-        '4' => Some(0xC),
-        'q' => Some(0x4),
-        'w' => Some(0x5),
-        'e' => Some(0x6),
-        'r' => Some(0xD),
-        'a' => Some(0x7),
-        's' => Some(0x8),
-        'd' => Some(0x9),
-        'f' => Some(0xE),
-        'z' => Some(0xA),
-        'x' => Some(0x0),
-        'c' => Some(0xB),
-        'v' => Some(0xF),
-        _ => None,
+/// Name of a key as used by [`Keymap`]
+fn key_name(code: KeyCode) -> Option<String> {
+    let name = match code {
+        KeyCode::Char(' ') => "Space",
+        KeyCode::Char(c) => return Some(c.to_string()),
+        KeyCode::Up => "Up",
+        KeyCode::Down => "Down",
+        KeyCode::Left => "Left",
+        KeyCode::Right => "Right",
+        KeyCode::Enter => "Enter",
+        KeyCode::Tab => "Tab",
+        KeyCode::Backspace => "Backspace",
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
+/// Title, authors and year of a ROM
+fn describe_rom(info: &RomInfo) -> String {
+    let mut description = info.title.clone();
+    if !info.authors.is_empty() {
+        description += &format!(" by {}", info.authors.join(", "));
+    }
+    if let Some(release) = &info.release {
+        description += &format!(" ({})", release);
     }
+    description
+}
+
+/// A preset name, or else the path of a keymap file
+fn load_keymap(name: &str) -> Result<Keymap, Box<dyn std::error::Error>> {
+    if let Some(keymap) = Keymap::preset(name) {
+        return Ok(keymap);
+    }
+    let text = fs::read_to_string(name)
+        .map_err(|err| format!("{}: not a keymap preset ({}) or file: {}", name, KEYMAP_PRESETS.join(", "), err))?;
+    let keymap = Keymap::parse(&text).map_err(|err| format!("{}: {}", name, err))?;
+    Ok(keymap)
 }
 
 /// Chip-8 Emulator
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// Path to the ROM file
+    /// Path to the ROM file, .8o files are compiled as Octo source
     #[arg(short, long)]
     rom: Option<PathBuf>,
 
@@ -208,33 +523,193 @@ struct Args {
     #[arg(short, long, default_value_t = PlatformType::Terminal)]
     platform: PlatformType,
 
-    #[arg(long, default_value_t = false)]
-    debug: bool,
+    /// Write every executed instruction to this file
+    #[arg(long)]
+    trace: Option<PathBuf>,
 
     #[arg(short, long)]
     cycles: Option<u32>,
 
     #[arg(short, long, default_value_t = 60)]
     fps: u64,
+
+    /// Instructions executed per frame. Defaults to the ROM database's
+    /// recommendation, or 10
+    #[arg(short, long)]
+    ipf: Option<u32>,
+
+    /// Instruction set to run. Defaults to the ROM database's platform, or
+    /// chip8
+    #[arg(short, long)]
+    mode: Option<ModeType>,
+
+    /// Interpreter whose behaviour to follow for ambiguous instructions.
+    /// Defaults to the one matching the selected mode
+    #[arg(short, long)]
+    quirks: Option<QuirksPreset>,
+
+    /// Seed for the random number generator. Defaults to the current time
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Record the buzzer to a WAV file
+    #[arg(long)]
+    wav: Option<PathBuf>,
+
+    /// Keyboard layout: qwerty, azerty, dvorak, colemak, octo or the path
+    /// of a keymap file. Defaults to qwerty, plus the arrows, space and
+    /// enter for ROMs whose controls are in the ROM database
+    #[arg(short, long)]
+    keymap: Option<String>,
+
+    /// Read ROM settings from this programs.json file of the CHIP-8
+    /// database instead of the built-in table
+    #[arg(long, value_name = "FILE")]
+    rom_db: Option<PathBuf>,
+
+    /// Print the selected keymap in the keymap file format and exit
+    #[arg(long)]
+    print_keymap: bool,
+
+    /// Save a screenshot once FRAMES frames have run, as PBM if FILE ends
+    /// in .pbm and PNG otherwise. F12 saves one at any time.
+    #[arg(long, num_args = 2, value_names = ["FRAMES", "FILE"])]
+    screenshot_after: Option<Vec<String>>,
+
+    /// Record the session as an animated GIF if FILE ends in .gif, or else
+    /// as PNG images in the directory FILE. F9 starts and stops a recording
+    /// at any time.
+    #[arg(long, value_name = "FILE")]
+    record: Option<PathBuf>,
+
+    /// Record the keys pressed on every frame to an input movie, along with
+    /// the ROM, seed and settings needed to replay it
+    #[arg(long, value_name = "FILE", conflicts_with = "replay_input")]
+    record_input: Option<PathBuf>,
+
+    /// Replay an input movie recorded with --record-input instead of
+    /// reading the keyboard, using its seed, mode, quirks and speed. Exits
+    /// when the movie ends.
+    #[arg(long, value_name = "FILE")]
+    replay_input: Option<PathBuf>,
+
+    /// Seconds of play kept to go back through by holding F8, 0 to
+    /// disable rewinding. Unavailable while recording or replaying input.
+    #[arg(long, default_value_t = 30)]
+    rewind_seconds: u64,
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
-    let chip8 = Chip8::new();
+    let screenshot_after = match args.screenshot_after.as_deref() {
+        Some([frames, file]) => {
+            let frames = frames
+                .parse()
+                .map_err(|err| format!("--screenshot-after: invalid frame count {}: {}", frames, err))?;
+            Some((frames, PathBuf::from(file)))
+        }
+        _ => None,
+    };
+    let rom = match &args.rom {
+        Some(rom) if rom.extension().is_some_and(|ext| ext == "8o") => {
+            let source = fs::read_to_string(rom)?;
+            let program = compile_octo(&source).map_err(|err| AssembleError {
+                file: Some(rom.clone()),
+                ..err
+            })?;
+            Some(program.rom)
+        }
+        Some(rom) => Some(fs::read(rom)?),
+        None => None,
+    };
+    let rom_db = match &args.rom_db {
+        Some(path) => RomDatabase::parse(&fs::read_to_string(path)?)
+            .map_err(|err| format!("{}: {}", path.display(), err))?,
+        None => RomDatabase::builtin(),
+    };
+    let info = rom.as_deref().and_then(|rom| rom_db.lookup(rom));
+    let keymap = match (&args.keymap, info) {
+        (Some(name), _) => load_keymap(name)?,
+        (None, Some(info)) => info.keymap(),
+        (None, None) => Keymap::qwerty(),
+    };
+    if args.print_keymap {
+        print!("{}", keymap);
+        return Ok(());
+    }
+    if let Some(info) = info {
+        println!("{}", describe_rom(info));
+    }
+    let replay_input = match &args.replay_input {
+        Some(path) => {
+            let movie = Movie::from_bytes(&fs::read(path)?)
+                .map_err(|err| format!("{}: {}", path.display(), err))?;
+            if !rom.as_ref().is_some_and(|rom| movie.matches_rom(rom)) {
+                return Err(format!("{}: recorded with a different ROM", path.display()).into());
+            }
+            Some(movie)
+        }
+        None => None,
+    };
+    // The database's platform only applies when neither is chosen
+    let (mode, quirks) = match (args.mode, args.quirks, info.and_then(RomInfo::platform)) {
+        (None, None, Some(platform)) => platform,
+        (mode, quirks, _) => {
+            let mode = mode.unwrap_or(ModeType::Chip8);
+            let quirks = quirks.unwrap_or(match mode {
+                ModeType::Chip8 => QuirksPreset::Vip,
+                ModeType::Schip => QuirksPreset::Schip,
+                ModeType::Xochip => QuirksPreset::Xochip,
+            });
+            (mode.into(), quirks.into())
+        }
+    };
+    let ipf = args
+        .ipf
+        .or(info.and_then(|info| info.tickrate))
+        .unwrap_or(10);
+    let seed = match args.seed {
+        Some(seed) => seed,
+        None => time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)?
+            .as_nanos() as u64,
+    };
+    // A replay runs as the recorded session did
+    let (mode, quirks, seed, ipf) = match &replay_input {
+        Some(movie) => (movie.mode, movie.quirks, movie.seed, movie.instructions_per_frame),
+        None => (mode, quirks, seed, ipf),
+    };
+    let record_input = match (args.record_input, &rom) {
+        (Some(path), Some(rom)) => Some((path, Movie::new(rom, seed, mode, quirks, ipf))),
+        (Some(_), None) => return Err("--record-input needs a ROM".into()),
+        (None, _) => None,
+    };
+    let mut chip8 = Chip8::with_rng(mode, quirks, Box::new(Xorshift64::new(seed)));
+    if let Some(path) = args.trace {
+        let out = io::BufWriter::new(fs::File::create(path)?);
+        chip8.set_observer(Box::new(TraceObserver { out }));
+    }
     let settings = Settings {
-        debug: args.debug,
         cycles: args.cycles,
         fps: args.fps,
+        ipf,
+        wav: args.wav,
+        keymap,
+        screenshot_after,
+        record: args.record,
+        record_input,
+        replay_input,
+        rewind_seconds: args.rewind_seconds,
     };
     let mut platform = match args.platform {
         PlatformType::Terminal => TerminalPlatform::new(chip8, settings),
     };
-    platform.init()?;
-    if let Some(rom) = args.rom {
-        let bytes = fs::read(rom)?;
-        platform.load(bytes);
+    if let Some(rom) = rom {
+        platform.load(rom)?;
     }
-    platform.run()?;
+    platform.init()?;
+    // Restore the terminal before reporting an error from the emulator
+    let result = platform.run();
     platform.cleanup()?;
-    Ok(())
+    result
 }
@@ -1,63 +1,135 @@
-use chip8::Chip8;
+use chip8::{Chip8, Keymap, Mode, Quirks, Xorshift64};
 use wasm_bindgen::prelude::*;
 use js_sys::Uint8Array;
 
 #[wasm_bindgen]
 pub struct WasmPlatform {
-    chip8: Chip8
+    chip8: Chip8,
+    keymap: Keymap,
 }
 
 #[wasm_bindgen]
 impl WasmPlatform {
     #[wasm_bindgen(constructor)]
-    pub fn new() -> WasmPlatform {
-        WasmPlatform {
-            chip8: Chip8::new(),
-        }
+    pub fn new(seed: u32) -> WasmPlatform {
+        WasmPlatform::with_mode(Mode::Chip8, Quirks::cosmac_vip(), seed)
+    }
+
+    #[wasm_bindgen]
+    pub fn superchip(seed: u32) -> WasmPlatform {
+        WasmPlatform::with_mode(Mode::SuperChip, Quirks::superchip(), seed)
+    }
+
+    #[wasm_bindgen]
+    pub fn xochip(seed: u32) -> WasmPlatform {
+        WasmPlatform::with_mode(Mode::XoChip, Quirks::xochip(), seed)
+    }
+
+    #[wasm_bindgen]
+    pub fn tick(&mut self) -> Result<(), JsError> {
+        self.chip8.tick()?;
+        Ok(())
     }
 
     #[wasm_bindgen]
-    pub fn tick(&mut self) {
-        self.chip8.tick();
+    pub fn tick_timers(&mut self) {
+        self.chip8.tick_timers();
     }
 
     #[wasm_bindgen]
-    pub fn keypress(&mut self, c: char, pressed: bool) {
-        if let Some(k) = ch_to_key(c) {
-            self.chip8.keypress(k, pressed);
+    pub fn run_frame(&mut self, instructions_per_frame: u32) -> Result<(), JsError> {
+        self.chip8.run_frame(instructions_per_frame)?;
+        Ok(())
+    }
+
+    /// Presses or releases the key named by a DOM `KeyboardEvent.key`
+    #[wasm_bindgen]
+    pub fn keypress(&mut self, key: &str, pressed: bool) {
+        if let Some(k) = self.keymap.get(key_name(key)) {
+            self.chip8.keypress(usize::from(k), pressed);
         }
     }
 
+    /// Switches to the keymap preset called `name`
+    #[wasm_bindgen]
+    pub fn set_keymap_preset(&mut self, name: &str) -> Result<(), JsError> {
+        self.keymap = Keymap::preset(name)
+            .ok_or_else(|| JsError::new(&format!("unknown keymap preset {}", name)))?;
+        Ok(())
+    }
+
+    /// Switches to a keymap in the text format of [`Keymap::parse`]
+    #[wasm_bindgen]
+    pub fn set_keymap(&mut self, text: &str) -> Result<(), JsError> {
+        self.keymap = Keymap::parse(text)?;
+        Ok(())
+    }
+
+    /// The current keymap in its text format
+    #[wasm_bindgen]
+    pub fn keymap(&self) -> String {
+        self.keymap.to_string()
+    }
+
     #[wasm_bindgen]
-    pub fn load_rom(&mut self, data: Uint8Array) {
+    pub fn load_rom(&mut self, data: Uint8Array) -> Result<(), JsError> {
         let v = data.to_vec();
-        self.chip8.load_rom(&v);
+        self.chip8.load_rom(&v)?;
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn width(&self) -> u16 {
+        self.chip8.width()
+    }
+
+    #[wasm_bindgen]
+    pub fn height(&self) -> u16 {
+        self.chip8.height()
+    }
+
+    #[wasm_bindgen]
+    pub fn pixel_at(&mut self, x: u16, y: u16) -> u8 {
+        self.chip8.pixel_at(x, y)
+    }
+
+    /// Colour indices of the whole display, row by row, copied in one call
+    #[wasm_bindgen]
+    pub fn framebuffer(&self) -> Vec<u8> {
+        self.chip8.framebuffer().to_vec()
+    }
+
+    /// Whether the sound timer is running
+    #[wasm_bindgen]
+    pub fn is_beeping(&self) -> bool {
+        self.chip8.is_beeping()
     }
 
+    /// Whether the display changed since the last call
     #[wasm_bindgen]
-    pub fn pixel_at(&mut self, x: u16, y: u16) -> bool {
-        self.chip8.pixel_at(x, y) == 1
+    pub fn frame_dirty(&mut self) -> bool {
+        self.chip8.frame_dirty()
+    }
+}
+
+impl WasmPlatform {
+    fn with_mode(mode: Mode, quirks: Quirks, seed: u32) -> WasmPlatform {
+        let rng = Box::new(Xorshift64::new(u64::from(seed)));
+        WasmPlatform {
+            chip8: Chip8::with_rng(mode, quirks, rng),
+            keymap: Keymap::qwerty(),
+        }
     }
 }
 
-fn ch_to_key(c: char) -> Option<usize> {
-    match c {
-        '1' => Some(0x1),
-        '2' => Some(0x2),
-        '3' => Some(0x3),
-        '4' => Some(0xC),
-        'q' => Some(0x4),
-        'w' => Some(0x5),
-        'e' => Some(0x6),
-        'r' => Some(0xD),
-        'a' => Some(0x7),
-        's' => Some(0x8),
-        'd' => Some(0x9),
-        'f' => Some(0xE),
-        'z' => Some(0xA),
-        'x' => Some(0x0),
-        'c' => Some(0xB),
-        'v' => Some(0xF),
-        _ => None,
+/// Translates DOM key names to the names used by [`Keymap`]
+fn key_name(key: &str) -> &str {
+    match key {
+        " " => "Space",
+        "ArrowUp" => "Up",
+        "ArrowDown" => "Down",
+        "ArrowLeft" => "Left",
+        "ArrowRight" => "Right",
+        _ => key,
     }
 }
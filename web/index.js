@@ -3,25 +3,68 @@ import init, * as wasm from "./pkg/wasm_platform.js";
 await init();
 const romInput = document.getElementById("romInput");
 const canvas = document.getElementById("canvas");
-const chip8 = new wasm.WasmPlatform();
+const modeInput = document.getElementById("modeInput");
+const keymapInput = document.getElementById("keymapInput");
+const palette = ["#000000", "#ffffff", "#aaaaaa", "#555555"];
+let chip8 = new wasm.WasmPlatform(0);
 const ctx = canvas.getContext("2d");
 let loaded = false;
-const chip8Width = 64;
-const chip8Height = 32;
-const clocksPerSec = 1000;
+let running = false;
+const framesPerSec = 60;
+const instructionsPerFrame = 10;
+let lastFrame = performance.now();
+// Created on the first ROM load, as browsers only allow audio after a user
+// gesture
+let audio = null;
 
-const animFrame = () => {
-    ctx.clearRect(0, 0, canvas.width, canvas.height);
-    const pixelWidth = canvas.width / chip8Width;
-    const pixelHeight = canvas.height / chip8Height;
-    if(loaded) {
+const startAudio = () => {
+    const context = new AudioContext();
+    const oscillator = context.createOscillator();
+    oscillator.type = "square";
+    oscillator.frequency.value = 440;
+    const gain = context.createGain();
+    gain.gain.value = 0;
+    oscillator.connect(gain).connect(context.destination);
+    oscillator.start();
+    return { context, gain };
+};
+
+const animFrame = (now) => {
+    if(running) {
+        // requestAnimationFrame may fire faster than 60 Hz on high refresh
+        // rate displays, so only step the emulator once per 60 Hz frame.
+        // Don't try to catch up after the tab was in the background.
+        if(now - lastFrame > 1000) {
+            lastFrame = now;
+        }
+        while(running && now - lastFrame >= 1000/framesPerSec) {
+            try {
+                chip8.run_frame(instructionsPerFrame);
+            } catch(err) {
+                console.error(err);
+                running = false;
+            }
+            lastFrame += 1000/framesPerSec;
+        }
+    }
+    if(audio) {
+        const volume = running && chip8.is_beeping() ? 0.25 : 0;
+        audio.gain.gain.setTargetAtTime(volume, audio.context.currentTime, 0.005);
+    }
+    // Redraw only when the display changed, reading it in a single call
+    if(loaded && chip8.frame_dirty()) {
+        ctx.clearRect(0, 0, canvas.width, canvas.height);
+        const chip8Width = chip8.width();
+        const chip8Height = chip8.height();
+        const pixelWidth = canvas.width / chip8Width;
+        const pixelHeight = canvas.height / chip8Height;
+        const framebuffer = chip8.framebuffer();
         for(let y=0;y<chip8Height;y++) {
             for(let x=0;x<chip8Width;x++) {
-                const pixelOn = chip8.pixel_at(x, y);
+                const pixel = framebuffer[x + y * chip8Width];
                 const pixelX = x * pixelWidth;
                 const pixelY = y * pixelHeight;
-                const pixelColor = pixelOn ? "#ffffff" : "#000000";
-                ctx.fillStyle = pixelColor;
+                ctx.fillStyle = palette[pixel];
                 ctx.fillRect(pixelX, pixelY, pixelWidth, pixelHeight);
             }
         }
@@ -33,26 +76,52 @@ const onInit = () => {
     canvas.width = 1280;
     canvas.height = 640;
     requestAnimationFrame(animFrame);
-    setInterval(() => {
-        if(loaded) {
-            chip8.tick();
-        }
-    }, 1000/clocksPerSec);
 };
 
 romInput.addEventListener("change", (ev) => {
+    if(!audio) {
+        audio = startAudio();
+    }
     const romFile = ev.target.files[0];
     const reader = new FileReader();
     const onReaderLoad = (loadEvent) => {
         const arr = new Uint8Array(loadEvent.target.result);
-        chip8.load_rom(arr);
+        const seed = Math.floor(Math.random() * 0xFFFFFFFF);
+        let platform;
+        switch(modeInput.value) {
+            case "schip":
+                platform = wasm.WasmPlatform.superchip(seed);
+                break;
+            case "xochip":
+                platform = wasm.WasmPlatform.xochip(seed);
+                break;
+            default:
+                platform = new wasm.WasmPlatform(seed);
+        }
+        platform.set_keymap_preset(keymapInput.value);
+        try {
+            platform.load_rom(arr);
+        } catch(err) {
+            // Keep running the current ROM
+            console.error(err);
+            platform.free();
+            return;
+        }
+        chip8.free();
+        chip8 = platform;
+        lastFrame = performance.now();
         loaded = true;
+        running = true;
     };
 
     reader.onload = onReaderLoad;
     reader.readAsArrayBuffer(romFile);
 });
 
+keymapInput.addEventListener("change", () => {
+    chip8.set_keymap_preset(keymapInput.value);
+});
+
 window.addEventListener("keydown", (ev) => {
     chip8.keypress(ev.key, true);
 });